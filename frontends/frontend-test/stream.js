@@ -531,6 +531,8 @@ class MseStream {
         if (!this.hasStartedStream) {
             this.hasStartedStream = true;
             this.webSocketMessageInit(event.data);
+        } else if (typeof event.data === "string") {
+            this.webSocketControl(JSON.parse(event.data));
         } else {
             var bytes = new Uint8Array(event.data);
             // this.networkBytes += bytes.length;
@@ -538,6 +540,25 @@ class MseStream {
         }
     }
 
+    webSocketControl(message) {
+        LOG.debug(`Received control message: ${message.type}`);
+
+        switch (message.type) {
+            case "codecChanged":
+                this.codec = message.contentType;
+                if (this.mseBuffer != null) {
+                    this.mseBuffer.changeType(this.codec);
+                }
+                break;
+            case "reconnecting":
+                LOG.warn("Stream stopped, waiting for it to come back");
+                break;
+            case "ended":
+                LOG.warn("Stream ended");
+                break;
+        }
+    }
+
     registerVideoEvents() {
 
     }
@@ -2,6 +2,17 @@ let accountInfo;
 let activeStream;
 let notificationsEnabled;
 
+// share links carry a token that grants access to a private stream
+const shareToken = new URLSearchParams(location.search).get("token");
+
+function withShareToken(url) {
+    if (shareToken == null) {
+        return url;
+    }
+
+    return `${url}?token=${encodeURIComponent(shareToken)}`;
+}
+
 navigator.serviceWorker.register("/sw.js");
 
 window.onload = async function(e) {
@@ -23,6 +34,13 @@ async function fragmentChanged() {
     switch (location.hash) {
         case "":
         case "#":
+            // share links point to /<stream> instead of /#<stream>
+            if (location.pathname.length > 1) {
+                console.log("Loading shared stream page");
+                await loadStreamPage(decodeURIComponent(location.pathname.substring(1)));
+                break;
+            }
+
             console.log("Loading home page");
             await loadHomePage();
             break;
@@ -79,8 +97,9 @@ async function loadStreamPage(stream) {
     streamPage.querySelector(".stream-name").innerText = stream;
 
     let video = streamPage.querySelector("video");
+    video.poster = withShareToken(`/api/stream/${stream}/thumbnail.jpg`);
     
-    activeStream = new MseStream(`wss://snail.video/api/live/${stream}`);
+    activeStream = new MseStream(withShareToken(`wss://snail.video/api/live/${stream}`));
     activeStream.statsContainer = streamPage.querySelector(".stream-page-overlay");
     activeStream.video = video;
 
@@ -204,7 +223,7 @@ function createStreamCard(stream) {
     let clone = streamTemplate.content.cloneNode(true);
 
     let video = clone.querySelector("video");
-    video.src = `/api/stream/${stream.name}/preview`
+    video.src = withShareToken(`/api/stream/${stream.name}/preview`);
     video.playbackRate = 2.0;
 
     let link = clone.querySelector(".streamLink");
@@ -3,16 +3,38 @@ self.addEventListener("push", (e) => {
 
     e.waitUntil(
         self.registration.showNotification(
-            data.name,
+            data.title,
             {
-                body: "started streaming",
+                body: data.body,
+                tag: data.tag,
                 icon: "/favicon.png",
+                image: data.image,
                 timestamp: data.started * 1000,
+                data: { url: data.url },
             }
         )
     );
 });
 
+self.addEventListener("notificationclick", (e) => {
+    e.notification.close();
+
+    let url = new URL(e.notification.data.url, self.location.origin).href;
+
+    e.waitUntil(
+        (async () => {
+            let windows = await clients.matchAll({ type: "window", includeUncontrolled: true });
+            for (let client of windows) {
+                if (client.url === url && "focus" in client) {
+                    return client.focus();
+                }
+            }
+
+            return clients.openWindow(url);
+        })()
+    );
+});
+
 self.addEventListener("pushsubscriptionchange", (e) => {
     console.log("Subscription expired");
 
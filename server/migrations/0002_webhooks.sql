@@ -0,0 +1,28 @@
+CREATE TABLE webhooks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL COLLATE NOCASE,
+
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    events TEXT NOT NULL,
+    all_streams INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+
+    FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
+
+CREATE TABLE webhook_deliveries (
+    id INTEGER PRIMARY KEY NOT NULL,
+    webhook_id INTEGER NOT NULL,
+
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    status_code INTEGER,
+    error TEXT,
+    delivered_at INTEGER NOT NULL,
+
+    FOREIGN KEY(webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+) STRICT;
+
+CREATE INDEX webhook_deliveries_webhook_id ON webhook_deliveries(webhook_id);
@@ -0,0 +1,7 @@
+CREATE TABLE web_push_keys (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+
+    public_key TEXT NOT NULL,
+    private_key TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+) STRICT;
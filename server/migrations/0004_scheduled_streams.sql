@@ -0,0 +1,14 @@
+CREATE TABLE scheduled_streams (
+    id INTEGER PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL COLLATE NOCASE,
+
+    title TEXT NOT NULL,
+    starts_at INTEGER NOT NULL,
+    reminder_sent INTEGER NOT NULL DEFAULT 0,
+
+    FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
+
+CREATE INDEX scheduled_streams_starts_at ON scheduled_streams(starts_at);
+
+ALTER TABLE users_notification_settings ADD COLUMN stream_ended INTEGER NOT NULL DEFAULT 0;
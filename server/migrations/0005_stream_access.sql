@@ -0,0 +1,9 @@
+CREATE TABLE stream_access (
+    username TEXT NOT NULL COLLATE NOCASE,
+
+    kind TEXT NOT NULL CHECK (kind IN ('user', 'group')),
+    value TEXT NOT NULL COLLATE NOCASE,
+
+    PRIMARY KEY(username, kind, value),
+    FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
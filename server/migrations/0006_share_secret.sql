@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN share_secret TEXT NOT NULL DEFAULT '';
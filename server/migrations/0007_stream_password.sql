@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN stream_password TEXT;
+ALTER TABLE users ADD COLUMN stream_password_nonce TEXT NOT NULL DEFAULT '';
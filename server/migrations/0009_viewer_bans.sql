@@ -0,0 +1,14 @@
+CREATE TABLE viewer_bans (
+    id INTEGER PRIMARY KEY,
+    username TEXT NOT NULL COLLATE NOCASE,
+
+    viewer TEXT COLLATE NOCASE,
+    address TEXT,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER,
+
+    CHECK (viewer IS NOT NULL OR address IS NOT NULL),
+    FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
+
+CREATE INDEX viewer_bans_username ON viewer_bans(username);
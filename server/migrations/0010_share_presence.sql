@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN share_presence INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+CREATE TABLE api_tokens (
+    id INTEGER PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL COLLATE NOCASE,
+
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    last_used_at INTEGER,
+
+    FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
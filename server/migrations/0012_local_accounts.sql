@@ -0,0 +1,8 @@
+CREATE TABLE local_accounts (
+    username TEXT PRIMARY KEY NOT NULL COLLATE NOCASE,
+
+    password_hash TEXT NOT NULL,
+    groups TEXT NOT NULL DEFAULT '',
+
+    FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
@@ -0,0 +1,17 @@
+ALTER TABLE users ADD COLUMN can_stream INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN invited_by TEXT COLLATE NOCASE;
+
+-- accounts created before invites existed were handed out manually
+UPDATE users SET can_stream = 1;
+
+CREATE TABLE invites (
+    code TEXT PRIMARY KEY NOT NULL,
+    created_by TEXT NOT NULL COLLATE NOCASE,
+
+    max_uses INTEGER NOT NULL,
+    uses INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+
+    FOREIGN KEY(created_by) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
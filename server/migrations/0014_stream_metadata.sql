@@ -0,0 +1,10 @@
+CREATE TABLE stream_metadata (
+    username TEXT PRIMARY KEY NOT NULL COLLATE NOCASE,
+
+    title TEXT,
+    description TEXT,
+    category TEXT,
+    tags TEXT NOT NULL DEFAULT '',
+
+    FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
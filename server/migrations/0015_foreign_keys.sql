@@ -0,0 +1,2 @@
+-- foreign keys were not enforced before, so deleted webhooks left their deliveries behind
+DELETE FROM webhook_deliveries WHERE webhook_id NOT IN (SELECT id FROM webhooks);
@@ -0,0 +1,6 @@
+-- accounts used to be created with the same stream key, so anyone could stream as them
+UPDATE users SET stream_key = hex(randomblob(32))
+WHERE stream_key = 'test123'
+    OR stream_key IN (SELECT stream_key FROM users GROUP BY stream_key HAVING COUNT(*) > 1);
+
+CREATE UNIQUE INDEX users_stream_key ON users(stream_key);
@@ -0,0 +1,674 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::Context;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Query},
+    http::{header, request::Parts},
+};
+use idlib::SecretKey;
+use jwt::{SignWithKey, VerifyWithKey};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use rusqlite::{params, OptionalExtension};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::debug;
+
+use crate::{
+    auth::Authorize,
+    limit::AttemptLimiter,
+    stream::Visibility,
+    token::{self, TokenScope},
+    Connection, Error,
+};
+
+/// The claims of a token that grants access to a single stream.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ShareClaims {
+    /// The stream the token grants access to.
+    stream: String,
+
+    /// When the token expires.
+    exp: i64,
+
+    /// The share secret of the stream when the token was created.
+    secret: String,
+}
+
+/// The claims of a session that unlocks a password protected stream.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct UnlockClaims {
+    /// The stream the session unlocks.
+    stream: String,
+
+    /// When the session expires.
+    exp: i64,
+
+    /// The password nonce of the stream when it was unlocked.
+    nonce: String,
+}
+
+/// Cookies unlocking a stream are named with this prefix followed by the stream name.
+const UNLOCK_COOKIE_PREFIX: &str = "scuffed-unlock-";
+
+/// How long unlocking a stream lasts, in seconds.
+pub const UNLOCK_DURATION: i64 = 24 * 60 * 60;
+
+/// How many times an address can try to unlock streams within [`UNLOCK_ATTEMPT_WINDOW`].
+const UNLOCK_ATTEMPTS_PER_ADDR: u32 = 10;
+
+const UNLOCK_ATTEMPT_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Deserialize)]
+struct ShareQuery {
+    token: Option<String>,
+}
+
+/// The person requesting to watch a stream.
+///
+/// ### Remarks
+///
+/// Extracting a viewer never fails, requests without a valid cookie or API token are anonymous.
+#[derive(Clone, Debug, Default)]
+pub struct Viewer {
+    /// The name of the viewer, if they are logged in.
+    pub name: Option<String>,
+
+    /// The hiveID groups of the viewer.
+    pub groups: Vec<String>,
+
+    /// The IP address the viewer is connecting from.
+    pub addr: Option<IpAddr>,
+
+    /// A valid, unexpired share token passed with the `token` query parameter.
+    share: Option<ShareClaims>,
+
+    /// Valid, unexpired sessions for password protected streams.
+    unlocked: Vec<UnlockClaims>,
+}
+
+impl Viewer {
+    fn is(&self, owner: &str) -> bool {
+        self.name
+            .as_ref()
+            .map(|name| name.eq_ignore_ascii_case(owner))
+            .unwrap_or(false)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Viewer {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let mut viewer = match Authorize::from_request_parts(parts, state).await {
+            Ok(Authorize(payload, ..)) => Viewer {
+                name: Some(payload.name),
+                groups: payload.groups,
+                ..Default::default()
+            },
+            Err(_) => Viewer::default(),
+        };
+
+        if let (Some(token), Some(db)) = (
+            token::bearer_token(parts),
+            parts.extensions.get::<Connection>(),
+        ) {
+            match token::authenticate(db, token).await {
+                Ok(Some((name, scopes))) if scopes.contains(&TokenScope::ReadStreams) => {
+                    viewer.name = Some(name);
+                }
+                Ok(_) => debug!("Rejected API token without the readStreams scope"),
+                Err(e) => debug!("Failed to authenticate API token: {e:?}"),
+            }
+        }
+        viewer.addr = client_addr(parts);
+
+        let token = Query::<ShareQuery>::from_request_parts(parts, state)
+            .await
+            .ok()
+            .and_then(|q| q.0.token);
+        let key = parts.extensions.get::<SecretKey>();
+        if let (Some(token), Some(key)) = (token, key) {
+            viewer.share = verify_token::<ShareClaims>(key, &token);
+        }
+
+        if let Some(key) = key {
+            viewer.unlocked = parts
+                .headers
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .filter(|(name, _)| name.starts_with(UNLOCK_COOKIE_PREFIX))
+                .filter_map(|(_, token)| verify_token::<UnlockClaims>(key, token))
+                .collect();
+        }
+
+        Ok(viewer)
+    }
+}
+
+/// Gets the address of the client, trusting `X-Forwarded-For` when connected through a reverse
+/// proxy on the same host.
+fn client_addr(parts: &Parts) -> Option<IpAddr> {
+    let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>()?.0.ip();
+    if !peer.is_loopback() {
+        return Some(peer);
+    }
+
+    let forwarded = parts
+        .headers
+        .get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|addr| addr.trim().parse().ok());
+
+    Some(forwarded.unwrap_or(peer))
+}
+
+/// Checks whether `viewer` is allowed to watch the stream owned by `owner`.
+///
+/// ### Remarks
+///
+/// The streamer can always watch. Banned viewers can never watch, and viewers with a valid
+/// share token can always watch otherwise. Private streams
+/// additionally require the viewer to be on the allowlist, and password protected streams
+/// require the viewer to have unlocked the stream.
+pub async fn authorize_viewer(
+    db: &Connection,
+    owner: &str,
+    visibility: Visibility,
+    viewer: &Viewer,
+) -> Result<(), Error> {
+    authorize(db, owner, visibility, viewer, true).await
+}
+
+/// Checks whether `viewer` is allowed to see information about the stream owned by `owner`.
+///
+/// ### Remarks
+///
+/// This is the same as [`authorize_viewer`], except that password protected streams don't need
+/// to be unlocked, so that viewers can find out that they need a password.
+pub async fn authorize_listing(
+    db: &Connection,
+    owner: &str,
+    visibility: Visibility,
+    viewer: &Viewer,
+) -> Result<(), Error> {
+    authorize(db, owner, visibility, viewer, false).await
+}
+
+async fn authorize(
+    db: &Connection,
+    owner: &str,
+    visibility: Visibility,
+    viewer: &Viewer,
+    require_unlocked: bool,
+) -> Result<(), Error> {
+    if viewer.is(owner) {
+        return Ok(());
+    }
+
+    if is_banned(db, owner.to_string(), viewer).await? {
+        return Err(Error::Banned);
+    }
+
+    if let Some(share) = &viewer.share {
+        if share.stream.eq_ignore_ascii_case(owner)
+            && share.secret == get_share_secret(db, owner.to_string()).await?
+        {
+            return Ok(());
+        }
+    }
+
+    if visibility == Visibility::Private {
+        authorize_private_viewer(db, owner, viewer).await?;
+    }
+
+    if !require_unlocked {
+        return Ok(());
+    }
+
+    if let Some(nonce) = get_stream_password_nonce(db, owner.to_string()).await? {
+        let unlocked = viewer
+            .unlocked
+            .iter()
+            .any(|u| u.stream.eq_ignore_ascii_case(owner) && u.nonce == nonce);
+
+        if !unlocked {
+            return Err(Error::StreamLocked);
+        }
+    }
+
+    Ok(())
+}
+
+async fn authorize_private_viewer(
+    db: &Connection,
+    owner: &str,
+    viewer: &Viewer,
+) -> Result<(), Error> {
+    let name = match &viewer.name {
+        Some(name) => name,
+        None => return Err(Error::Unathorized),
+    };
+
+    let access = get_stream_access(db, owner.to_string()).await?;
+
+    let allowed = access.users.iter().any(|u| u.eq_ignore_ascii_case(name))
+        || access
+            .groups
+            .iter()
+            .any(|g| viewer.groups.iter().any(|vg| vg.eq_ignore_ascii_case(g)));
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+/// Who is allowed to watch a private stream.
+#[derive(Debug, Default)]
+pub struct StreamAccess {
+    pub users: Vec<String>,
+    pub groups: Vec<String>,
+}
+
+pub async fn get_stream_access(db: &Connection, owner: String) -> anyhow::Result<StreamAccess> {
+    db.call(move |conn| {
+        let mut stmt = conn.prepare("SELECT kind, value FROM stream_access WHERE username = ?1")?;
+
+        let mut access = StreamAccess::default();
+        for row in stmt.query_map(params![owner], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })? {
+            let (kind, value) = row?;
+            match kind.as_str() {
+                "user" => access.users.push(value),
+                _ => access.groups.push(value),
+            }
+        }
+
+        Ok::<_, rusqlite::Error>(access)
+    })
+    .await
+    .context("Failed to query stream access")
+}
+
+pub async fn set_stream_access(
+    db: &Connection,
+    owner: String,
+    access: StreamAccess,
+) -> anyhow::Result<()> {
+    db.call(move |conn| {
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "DELETE FROM stream_access WHERE username = ?1",
+            params![&owner],
+        )?;
+
+        for user in &access.users {
+            tx.execute(
+                "INSERT OR IGNORE INTO stream_access (username, kind, value) VALUES (?1, 'user', ?2)",
+                params![&owner, user],
+            )?;
+        }
+
+        for group in &access.groups {
+            tx.execute(
+                "INSERT OR IGNORE INTO stream_access (username, kind, value) VALUES (?1, 'group', ?2)",
+                params![&owner, group],
+            )?;
+        }
+
+        tx.commit()
+    })
+    .await
+    .context("Failed to update stream access")
+}
+
+/// Creates a token that grants access to the stream of `owner` until `expires_at`.
+pub async fn create_share_token(
+    db: &Connection,
+    key: &SecretKey,
+    owner: String,
+    expires_at: i64,
+) -> anyhow::Result<String> {
+    let claims = ShareClaims {
+        secret: get_share_secret(db, owner.clone()).await?,
+        stream: owner,
+        exp: expires_at,
+    };
+
+    claims
+        .sign_with_key(&key.0)
+        .context("Failed to sign share token")
+}
+
+/// Invalidates all share tokens of the stream of `owner` by changing its share secret.
+pub async fn rotate_share_secret(db: &Connection, owner: String) -> anyhow::Result<()> {
+    let mut secret_bytes = [0u8; 16];
+    StdRng::from_entropy().fill_bytes(&mut secret_bytes[..]);
+    let secret = base64::encode(secret_bytes);
+
+    db.call(move |conn| {
+        conn.execute(
+            "UPDATE users SET share_secret = ?1 WHERE username = ?2",
+            params![secret, owner],
+        )
+    })
+    .await
+    .context("Failed to update share secret")?;
+
+    Ok(())
+}
+
+trait Expiring {
+    fn expires_at(&self) -> i64;
+}
+
+impl Expiring for ShareClaims {
+    fn expires_at(&self) -> i64 {
+        self.exp
+    }
+}
+
+impl Expiring for UnlockClaims {
+    fn expires_at(&self) -> i64 {
+        self.exp
+    }
+}
+
+fn verify_token<T: DeserializeOwned + Expiring>(key: &SecretKey, token: &str) -> Option<T> {
+    let claims: T = match token.verify_with_key(&key.0) {
+        Ok(claims) => claims,
+        Err(e) => {
+            debug!("Rejected token: {e}");
+            return None;
+        }
+    };
+
+    if claims.expires_at() < OffsetDateTime::now_utc().unix_timestamp() {
+        debug!("Rejected expired token");
+        return None;
+    }
+
+    Some(claims)
+}
+
+async fn get_share_secret(db: &Connection, owner: String) -> anyhow::Result<String> {
+    db.call(move |conn| {
+        conn.query_row(
+            "SELECT share_secret FROM users WHERE username = ?1",
+            params![owner],
+            |row| row.get(0),
+        )
+    })
+    .await
+    .context("Failed to query share secret")
+}
+
+/// Sets or clears the password needed to watch the stream of `owner`.
+///
+/// ### Remarks
+///
+/// Changing the password ends all existing unlock sessions.
+pub async fn set_stream_password(
+    db: &Connection,
+    owner: String,
+    password: Option<String>,
+) -> anyhow::Result<()> {
+    let hash = match password {
+        Some(password) => {
+            let salt = SaltString::generate(&mut OsRng);
+            let hash = Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map_err(|e| anyhow::anyhow!("Failed to hash stream password: {e}"))?;
+
+            Some(hash.to_string())
+        }
+        None => None,
+    };
+
+    let mut nonce_bytes = [0u8; 16];
+    StdRng::from_entropy().fill_bytes(&mut nonce_bytes[..]);
+    let nonce = base64::encode(nonce_bytes);
+
+    db.call(move |conn| {
+        conn.execute(
+            "UPDATE users SET stream_password = ?1, stream_password_nonce = ?2 WHERE username = ?3",
+            params![hash, nonce, owner],
+        )
+    })
+    .await
+    .context("Failed to update stream password")?;
+
+    Ok(())
+}
+
+/// Limits how often stream passwords can be guessed from an address.
+///
+/// ### Remarks
+///
+/// Attempts are not limited per stream, since then anyone could lock everyone else out of a
+/// stream by guessing wrong on purpose.
+#[derive(Clone)]
+pub struct UnlockLimiter {
+    by_addr: AttemptLimiter,
+}
+
+impl UnlockLimiter {
+    pub fn new() -> Self {
+        UnlockLimiter {
+            by_addr: AttemptLimiter::new(UNLOCK_ATTEMPTS_PER_ADDR, UNLOCK_ATTEMPT_WINDOW),
+        }
+    }
+
+    /// Records an attempt to unlock `stream` from `addr`.
+    pub fn check(&self, addr: Option<IpAddr>, stream: &str) -> Result<(), Error> {
+        let addr = addr.map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
+
+        // counted over all streams so that spreading guesses over streams doesn't help
+        if self.by_addr.try_attempt(&addr) {
+            Ok(())
+        } else {
+            debug!("Rate limited unlocking {stream:?} from {addr}");
+            Err(Error::TooManyAttempts)
+        }
+    }
+}
+
+/// Checks `password` against the stream of `owner`, returning the name of the unlock cookie
+/// and its value if it matches.
+pub async fn unlock_stream(
+    db: &Connection,
+    key: &SecretKey,
+    owner: String,
+    password: String,
+) -> Result<(String, String), Error> {
+    let stored = {
+        let owner = owner.clone();
+        db.call(move |conn| {
+            conn.query_row(
+                "SELECT stream_password, stream_password_nonce FROM users WHERE username = ?1",
+                params![owner],
+                |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()
+        })
+        .await
+        .context("Failed to query stream password")?
+    };
+
+    let (hash, nonce) = match stored {
+        Some((Some(hash), nonce)) => (hash, nonce),
+        _ => return Err(Error::NotFound),
+    };
+
+    let hash = PasswordHash::new(&hash)
+        .map_err(|e| anyhow::anyhow!("Failed to parse stream password hash: {e}"))?;
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        return Err(Error::InvalidPassword);
+    }
+
+    let cookie_name = format!("{UNLOCK_COOKIE_PREFIX}{}", owner.to_lowercase());
+    let claims = UnlockClaims {
+        stream: owner.to_lowercase(),
+        exp: OffsetDateTime::now_utc().unix_timestamp() + UNLOCK_DURATION,
+        nonce,
+    };
+    let token = claims
+        .sign_with_key(&key.0)
+        .context("Failed to sign unlock token")?;
+
+    Ok((cookie_name, token))
+}
+
+/// Gets the streamers that have a password set.
+pub async fn get_locked_streams(db: &Connection) -> anyhow::Result<Vec<String>> {
+    db.call(|conn| {
+        let mut stmt =
+            conn.prepare("SELECT username FROM users WHERE stream_password IS NOT NULL")?;
+
+        let rows = stmt
+            .query_map(params![], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok::<_, rusqlite::Error>(rows)
+    })
+    .await
+    .context("Failed to query locked streams")
+}
+
+async fn get_stream_password_nonce(
+    db: &Connection,
+    owner: String,
+) -> anyhow::Result<Option<String>> {
+    db.call(move |conn| {
+        conn.query_row(
+            "SELECT stream_password_nonce FROM users \
+            WHERE username = ?1 AND stream_password IS NOT NULL",
+            params![owner],
+            |row| row.get(0),
+        )
+        .optional()
+    })
+    .await
+    .context("Failed to query stream password")
+}
+
+/// A viewer who is not allowed to watch a stream.
+#[derive(Debug)]
+pub struct ViewerBan {
+    pub id: i64,
+    pub viewer: Option<String>,
+    pub addr: Option<IpAddr>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+/// Bans a viewer from the stream of `owner` until `expires_at`, or forever.
+///
+/// ### Remarks
+///
+/// Logged in viewers are banned by name and anonymous viewers by IP address.
+pub async fn ban_viewer(
+    db: &Connection,
+    owner: String,
+    viewer: Option<String>,
+    addr: Option<IpAddr>,
+    expires_at: Option<i64>,
+) -> anyhow::Result<()> {
+    let addr = if viewer.is_some() {
+        None
+    } else {
+        Some(addr.context("Can't ban an anonymous viewer without an address")?)
+    };
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    db.call(move |conn| {
+        conn.execute(
+            "INSERT INTO viewer_bans (username, viewer, address, created_at, expires_at) \
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![owner, viewer, addr.map(|a| a.to_string()), now, expires_at],
+        )
+    })
+    .await
+    .context("Failed to insert viewer ban")?;
+
+    Ok(())
+}
+
+/// Gets the bans of the stream of `owner` that have not expired.
+pub async fn get_viewer_bans(db: &Connection, owner: String) -> anyhow::Result<Vec<ViewerBan>> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    db.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, viewer, address, created_at, expires_at FROM viewer_bans \
+            WHERE username = ?1 AND (expires_at IS NULL OR expires_at > ?2) \
+            ORDER BY created_at DESC",
+        )?;
+
+        let bans = stmt
+            .query_map(params![owner, now], |row| {
+                Ok(ViewerBan {
+                    id: row.get(0)?,
+                    viewer: row.get(1)?,
+                    addr: row
+                        .get::<_, Option<String>>(2)?
+                        .and_then(|addr| addr.parse().ok()),
+                    created_at: row.get(3)?,
+                    expires_at: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok::<_, rusqlite::Error>(bans)
+    })
+    .await
+    .context("Failed to query viewer bans")
+}
+
+/// Lifts a ban, returning `false` if the stream of `owner` has no ban with the given ID.
+pub async fn remove_viewer_ban(db: &Connection, owner: String, id: i64) -> anyhow::Result<bool> {
+    let removed = db
+        .call(move |conn| {
+            conn.execute(
+                "DELETE FROM viewer_bans WHERE id = ?1 AND username = ?2",
+                params![id, owner],
+            )
+        })
+        .await
+        .context("Failed to delete viewer ban")?;
+
+    Ok(removed > 0)
+}
+
+async fn is_banned(db: &Connection, owner: String, viewer: &Viewer) -> anyhow::Result<bool> {
+    let bans = get_viewer_bans(db, owner).await?;
+
+    Ok(bans.iter().any(|ban| {
+        let name_matches = match (&ban.viewer, &viewer.name) {
+            (Some(banned), Some(name)) => banned.eq_ignore_ascii_case(name),
+            _ => false,
+        };
+
+        name_matches || (ban.addr.is_some() && ban.addr == viewer.addr)
+    }))
+}
@@ -1,29 +1,63 @@
 use anyhow::Context;
 use axum::{
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::{delete, get, post, put},
     Extension, Json, Router,
 };
 
-use idlib::{AuthorizationRejection, AuthorizeCookie, NoGroups};
+use idlib::SecretKey;
 
-use rusqlite::params;
+use rusqlite::{params, types::ValueRef};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 
 use rand::{rngs::StdRng, RngCore, SeedableRng};
+use time::OffsetDateTime;
 use tokio_rusqlite::Connection;
 use utoipa::ToSchema;
 
-use crate::Error;
+use crate::{
+    access,
+    auth::{self, AuthBackend, Authorize},
+    event::StreamEvent,
+    metadata::{self, StreamMetadata},
+    presence,
+    stream::LiveStreamService,
+    token::{self, ApiAuth, ManageKeys},
+    Error,
+};
 
 pub fn api_route() -> Router {
     Router::new()
-        .route("/", get(get_account))
+        .route("/", get(get_account).delete(delete_account))
+        .route("/export", get(get_account_export))
         .route("/login", get(get_login))
         .route("/key", post(post_generate_stream_key))
+        .route("/stream", get(get_stream_metadata).put(put_stream_metadata))
+        .route(
+            "/stream/access",
+            get(get_stream_access).put(put_stream_access),
+        )
+        .route(
+            "/stream/share",
+            post(post_share_link).delete(delete_share_links),
+        )
+        .route("/stream/password", put(put_stream_password))
+        .route("/tokens", get(token::get_tokens).post(token::post_token))
+        .route("/tokens/:id", delete(token::delete_token))
+        .route(
+            "/stream/presence",
+            get(get_presence_settings).put(put_presence_settings),
+        )
 }
 
+/// The longest a share link can be valid for, in seconds.
+const MAX_SHARE_DURATION: i64 = 7 * 24 * 60 * 60;
+
+/// How long a share link is valid for when not specified, in seconds.
+const DEFAULT_SHARE_DURATION: i64 = 24 * 60 * 60;
+
 /// Information about an account.
 #[derive(ToSchema, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -33,6 +67,9 @@ pub struct AccountInfo {
 
     /// The account's stream key.
     stream_key: String,
+
+    /// Whether the account is allowed to stream, which requires redeeming an invite.
+    can_stream: bool,
 }
 
 /// Gets account info.
@@ -45,11 +82,11 @@ pub struct AccountInfo {
     )
 )]
 pub async fn get_account(
-    cookie: Result<AuthorizeCookie<NoGroups>, AuthorizationRejection>,
+    auth: Result<ApiAuth<ManageKeys>, Response>,
     Extension(db): Extension<Connection>,
 ) -> impl IntoResponse {
-    let AuthorizeCookie(payload, maybe_token, ..) = if let Ok(cookie) = cookie {
-        cookie
+    let ApiAuth(payload, maybe_token, ..) = if let Ok(auth) = auth {
+        auth
     } else {
         return Error::NotFound.into_response();
     };
@@ -63,6 +100,7 @@ pub async fn get_account(
                     Json(AccountInfo {
                         name: a.username,
                         stream_key: a.stream_key,
+                        can_stream: a.can_stream,
                     })
                 })
                 .map_err(|_| Error::NotFound)
@@ -71,33 +109,45 @@ pub async fn get_account(
 }
 
 /// Logs in to the site by redirecting to hiveID.
+///
+/// ### Remarks
+///
+/// When running with the `local` or `dev` auth backend this redirects to the local login page
+/// instead.
 #[utoipa::path(
     get,
     path = "/api/account/login",
     responses(
-        (status = 302, description = "Redirects to hiveID successfully"),
+        (status = 302, description = "Redirects to the login page successfully"),
     )
 )]
 pub async fn get_login(
-    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<NoGroups>,
+    auth: Result<Authorize, Response>,
+    Extension(backend): Extension<AuthBackend>,
 ) -> impl IntoResponse {
-    maybe_token.wrap(|| {})
+    match auth {
+        Ok(Authorize(_payload, maybe_token)) => maybe_token.wrap(|| {}),
+        Err(_) if backend != AuthBackend::HiveId => Redirect::to("/auth/login").into_response(),
+        Err(rejection) => rejection,
+    }
 }
 
 struct Account {
     username: String,
     stream_key: String,
+    can_stream: bool,
 }
 
 async fn get_account_by_username(db: Connection, username: String) -> anyhow::Result<Account> {
     db.call(move |conn| {
         conn.query_row(
-            "SELECT username, stream_key FROM users WHERE username = ?1",
+            "SELECT username, stream_key, can_stream FROM users WHERE username = ?1",
             params![username],
             |r| {
                 Ok(Account {
                     username: r.get(0).unwrap(),
                     stream_key: r.get(1).unwrap(),
+                    can_stream: r.get(2).unwrap(),
                 })
             },
         )
@@ -115,7 +165,7 @@ async fn get_account_by_username(db: Connection, username: String) -> anyhow::Re
     )
 )]
 pub async fn post_generate_stream_key(
-    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<NoGroups>,
+    ApiAuth(payload, maybe_token, ..): ApiAuth<ManageKeys>,
     Extension(db): Extension<Connection>,
 ) -> impl IntoResponse {
     maybe_token
@@ -127,26 +177,514 @@ pub async fn post_generate_stream_key(
         .await
 }
 
-async fn generate_stream_key(db: Connection, username: String) -> anyhow::Result<()> {
+/// Replaces the stream key of an account, returning `false` if the account does not exist.
+pub async fn generate_stream_key(db: Connection, username: String) -> anyhow::Result<bool> {
     let new_stream_key = get_new_stream_key();
 
     db.call(move |conn| {
-        conn.execute(
-            "UPDATE users \
+        let updated = conn
+            .execute(
+                "UPDATE users \
             SET stream_key = ?1
             WHERE username = ?2",
-            params![new_stream_key, username],
-        )
-        .context("Failed to update stream key")?;
+                params![new_stream_key, username],
+            )
+            .context("Failed to update stream key")?;
 
-        Ok(())
+        Ok(updated > 0)
     })
     .await
 }
 
-fn get_new_stream_key() -> String {
+/// Gets the metadata of the account's stream.
+#[utoipa::path(
+    get,
+    path = "/api/account/stream",
+    responses(
+        (status = 200, description = "Got stream metadata successfully", body = StreamMetadata),
+    )
+)]
+pub async fn get_stream_metadata(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let metadata = metadata::get_metadata(&db, payload.name).await?;
+
+            Ok::<_, Error>(Json(metadata))
+        })
+        .await
+}
+
+/// Replaces the metadata of the account's stream.
+///
+/// ### Remarks
+///
+/// The metadata is kept between streams and can be changed while live.
+#[utoipa::path(
+    put,
+    path = "/api/account/stream",
+    request_body = StreamMetadata,
+    responses(
+        (status = 200, description = "Changed stream metadata successfully"),
+        (status = 400, description = "A field is too long or there are too many tags"),
+    )
+)]
+pub async fn put_stream_metadata(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+    Json(body): Json<StreamMetadata>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let metadata = body.validate()?;
+            metadata::set_metadata(&db, payload.name.clone(), metadata).await?;
+
+            svc.publish(StreamEvent::MetadataChanged {
+                stream: payload.name,
+            });
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+/// Who is allowed to watch the account's stream when it is private.
+#[derive(ToSchema, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamAccessInfo {
+    /// The names of users that can watch the stream.
+    users: Vec<String>,
+
+    /// The hiveID groups whose members can watch the stream.
+    groups: Vec<String>,
+}
+
+/// Gets who is allowed to watch the account's private stream.
+#[utoipa::path(
+    get,
+    path = "/api/account/stream/access",
+    responses(
+        (status = 200, description = "Lists stream access successfully", body = StreamAccessInfo),
+    )
+)]
+pub async fn get_stream_access(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let access = access::get_stream_access(&db, payload.name).await?;
+
+            Ok::<_, Error>(Json(StreamAccessInfo {
+                users: access.users,
+                groups: access.groups,
+            }))
+        })
+        .await
+}
+
+/// Replaces who is allowed to watch the account's private stream.
+///
+/// ### Remarks
+///
+/// This only has an effect when streaming with the `private` visibility.
+#[utoipa::path(
+    put,
+    path = "/api/account/stream/access",
+    request_body = StreamAccessInfo,
+    responses(
+        (status = 200, description = "Changed stream access successfully"),
+    )
+)]
+pub async fn put_stream_access(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Json(body): Json<StreamAccessInfo>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let access = access::StreamAccess {
+                users: body.users,
+                groups: body.groups,
+            };
+            access::set_stream_access(&db, payload.name, access).await?;
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+/// A request to create a share link.
+#[derive(ToSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewShareLink {
+    /// How many seconds the link is valid for, at most a week. Defaults to a day.
+    expires_in: Option<i64>,
+}
+
+/// A link that lets anyone watch the account's stream until it expires.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareLink {
+    /// The token to pass in the `token` query parameter.
+    token: String,
+
+    /// The stream page with the token included.
+    url: String,
+
+    /// When the link stops working.
+    expires_at: i64,
+}
+
+/// Creates a signed link that grants access to the account's stream.
+///
+/// ### Remarks
+///
+/// The token is accepted by `/api/live/{stream}` and `/api/stream/{stream}/preview`, which
+/// lets guests without an account watch private streams.
+#[utoipa::path(
+    post,
+    path = "/api/account/stream/share",
+    request_body = NewShareLink,
+    responses(
+        (status = 200, description = "Created share link successfully", body = ShareLink),
+        (status = 400, description = "The expiry is not within a week"),
+    )
+)]
+pub async fn post_share_link(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Extension(key): Extension<SecretKey>,
+    Json(body): Json<NewShareLink>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let expires_in = body.expires_in.unwrap_or(DEFAULT_SHARE_DURATION);
+            if expires_in <= 0 || expires_in > MAX_SHARE_DURATION {
+                return Err(Error::InvalidTimeframe);
+            }
+
+            let expires_at = OffsetDateTime::now_utc().unix_timestamp() + expires_in;
+            let name = payload.name;
+            let token = access::create_share_token(&db, &key, name.clone(), expires_at).await?;
+
+            Ok(Json(ShareLink {
+                url: format!("/{name}?token={token}"),
+                token,
+                expires_at,
+            }))
+        })
+        .await
+}
+
+/// Revokes all share links of the account's stream.
+#[utoipa::path(
+    delete,
+    path = "/api/account/stream/share",
+    responses(
+        (status = 200, description = "Revoked share links successfully"),
+    )
+)]
+pub async fn delete_share_links(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            access::rotate_share_secret(&db, payload.name).await?;
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+/// A request to change the password needed to watch the account's stream.
+#[derive(ToSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamPassword {
+    /// The new password, or `null` to remove the password.
+    password: Option<String>,
+}
+
+/// Sets or removes the password needed to watch the account's stream.
+///
+/// ### Remarks
+///
+/// Viewers have to unlock the stream with `/api/stream/{stream}/unlock` before watching.
+/// Changing the password locks out everyone that unlocked the stream before.
+#[utoipa::path(
+    put,
+    path = "/api/account/stream/password",
+    request_body = StreamPassword,
+    responses(
+        (status = 200, description = "Changed stream password successfully"),
+        (status = 400, description = "The password is empty"),
+    )
+)]
+pub async fn put_stream_password(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Json(body): Json<StreamPassword>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            if matches!(&body.password, Some(password) if password.is_empty()) {
+                return Err(Error::EmptyPassword);
+            }
+
+            access::set_stream_password(&db, payload.name, body.password).await?;
+
+            Ok(StatusCode::OK)
+        })
+        .await
+}
+
+/// Whether the account shares who is watching its stream.
+#[derive(ToSchema, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PresenceSettings {
+    /// Whether the names of logged in viewers are shown to everyone watching the stream.
+    share: bool,
+}
+
+/// Gets whether the account shares who is watching its stream.
+#[utoipa::path(
+    get,
+    path = "/api/account/stream/presence",
+    responses(
+        (status = 200, description = "Got presence settings successfully", body = PresenceSettings),
+    )
+)]
+pub async fn get_presence_settings(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let share = presence::is_shared(&db, payload.name).await?;
+
+            Ok::<_, Error>(Json(PresenceSettings { share }))
+        })
+        .await
+}
+
+/// Changes whether the account shares who is watching its stream.
+///
+/// ### Remarks
+///
+/// This is off by default. When on, the names of logged in viewers are included in
+/// `/api/stream` and pushed by `/api/stream/{stream}/presence`.
+#[utoipa::path(
+    put,
+    path = "/api/account/stream/presence",
+    request_body = PresenceSettings,
+    responses(
+        (status = 200, description = "Changed presence settings successfully"),
+    )
+)]
+pub async fn put_presence_settings(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Json(body): Json<PresenceSettings>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            presence::set_shared(&db, payload.name, body.share).await?;
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+pub fn get_new_stream_key() -> String {
     let mut secret_bytes = [0u8; 32];
     StdRng::from_entropy().fill_bytes(&mut secret_bytes[..]);
 
     base64::encode(secret_bytes)
 }
+
+/// The tables holding data of an account, and the column naming the account.
+const ACCOUNT_TABLES: &[(&str, &str)] = &[
+    ("users", "username"),
+    ("users_notification_settings", "username"),
+    ("notification_subscriptions", "username"),
+    ("webhooks", "username"),
+    ("scheduled_streams", "username"),
+    ("stream_access", "username"),
+    ("viewer_bans", "username"),
+    ("api_tokens", "username"),
+    ("local_accounts", "username"),
+    ("invites", "created_by"),
+    ("stream_metadata", "username"),
+];
+
+/// Columns holding hashes and server secrets, which are left out of exports.
+const SECRET_COLUMNS: &[&str] = &[
+    "share_secret",
+    "stream_password",
+    "stream_password_nonce",
+    "token_hash",
+    "password_hash",
+];
+
+/// Deletes the account of the current user and everything stored about it.
+///
+/// ### Remarks
+///
+/// This stops the user's stream if it is live and removes them from other streamers'
+/// allowlists and bans. Logging in again creates a new, empty account.
+#[utoipa::path(
+    delete,
+    path = "/api/account",
+    responses(
+        (status = 200, description = "Deleted account successfully"),
+    )
+)]
+pub async fn delete_account(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            svc.force_stop_stream(&payload.name).await;
+
+            delete_account_data(&db, payload.name).await?;
+
+            // logs out of the local auth backends
+            let cookie = auth::logout_cookie();
+
+            Ok::<_, Error>((StatusCode::OK, [(header::SET_COOKIE, cookie)]))
+        })
+        .await
+}
+
+/// Exports everything stored about the current user as JSON.
+///
+/// ### Remarks
+///
+/// Password hashes and server secrets are left out.
+#[utoipa::path(
+    get,
+    path = "/api/account/export",
+    responses(
+        (status = 200, description = "Exported account successfully", content_type = "application/json"),
+    )
+)]
+pub async fn get_account_export(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let name = payload.name;
+            let disposition = format!("attachment; filename=\"scuffed-{name}.json\"");
+            let tables = export_account_data(&db, name).await?;
+
+            let export = json!({
+                "exportedAt": OffsetDateTime::now_utc().unix_timestamp(),
+                "tables": tables,
+            });
+
+            Ok::<_, Error>(([(header::CONTENT_DISPOSITION, disposition)], Json(export)))
+        })
+        .await
+}
+
+async fn delete_account_data(db: &Connection, name: String) -> anyhow::Result<()> {
+    db.call(move |conn| {
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "DELETE FROM webhook_deliveries \
+            WHERE webhook_id IN (SELECT id FROM webhooks WHERE username = ?1)",
+            params![name],
+        )?;
+
+        // users is listed first, but everything referencing it has to go before it
+        for (table, column) in ACCOUNT_TABLES.iter().rev() {
+            tx.execute(
+                &format!("DELETE FROM {table} WHERE {column} = ?1"),
+                params![name],
+            )?;
+        }
+
+        tx.execute(
+            "DELETE FROM stream_access WHERE kind = 'user' AND value = ?1",
+            params![name],
+        )?;
+        tx.execute("DELETE FROM viewer_bans WHERE viewer = ?1", params![name])?;
+        tx.execute(
+            "UPDATE users SET invited_by = NULL WHERE invited_by = ?1",
+            params![name],
+        )?;
+
+        tx.commit()
+    })
+    .await
+    .context("Failed to delete account")
+}
+
+async fn export_account_data(db: &Connection, name: String) -> anyhow::Result<Map<String, Value>> {
+    db.call(move |conn| {
+        let mut tables = Map::new();
+
+        for (table, column) in ACCOUNT_TABLES {
+            let rows = export_rows(
+                conn,
+                &format!("SELECT * FROM {table} WHERE {column} = ?1"),
+                &name,
+            )?;
+            tables.insert(table.to_string(), Value::Array(rows));
+        }
+
+        let deliveries = export_rows(
+            conn,
+            "SELECT * FROM webhook_deliveries \
+            WHERE webhook_id IN (SELECT id FROM webhooks WHERE username = ?1)",
+            &name,
+        )?;
+        tables.insert("webhook_deliveries".into(), Value::Array(deliveries));
+
+        Ok::<_, rusqlite::Error>(tables)
+    })
+    .await
+    .context("Failed to export account")
+}
+
+fn export_rows(conn: &rusqlite::Connection, sql: &str, name: &str) -> rusqlite::Result<Vec<Value>> {
+    let mut stmt = conn.prepare(sql)?;
+    let columns = stmt
+        .column_names()
+        .into_iter()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    let mut rows = stmt.query(params![name])?;
+    let mut exported = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut object = Map::new();
+
+        for (idx, column) in columns.iter().enumerate() {
+            if SECRET_COLUMNS.contains(&column.as_str()) {
+                continue;
+            }
+
+            let value = match row.get_ref(idx)? {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(i) => json!(i),
+                ValueRef::Real(f) => json!(f),
+                ValueRef::Text(t) => json!(String::from_utf8_lossy(t)),
+                ValueRef::Blob(b) => json!(base64::encode(b)),
+            };
+            object.insert(column.clone(), value);
+        }
+
+        exported.push(Value::Object(object));
+    }
+
+    Ok(exported)
+}
@@ -0,0 +1,239 @@
+use std::env;
+
+use anyhow::Context;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
+};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tokio_rusqlite::Connection;
+use utoipa::ToSchema;
+
+use crate::{
+    account,
+    auth::Authorize,
+    stream::{LiveStreamService, Visibility},
+    Error,
+};
+
+pub fn api_route() -> Router {
+    Router::new()
+        .route("/stream", get(get_all_streams))
+        .route("/stream/:stream/stop", post(post_stop_stream))
+        .route("/account/:account/streaming", put(put_streaming_permission))
+        .route("/account/:account/key", delete(delete_stream_key))
+}
+
+/// The hiveID group whose members are admins when `ADMIN_GROUP` is not set.
+const DEFAULT_ADMIN_GROUP: &str = "scuffed-admin";
+
+fn admin_group() -> String {
+    env::var("ADMIN_GROUP").unwrap_or_else(|_| DEFAULT_ADMIN_GROUP.to_string())
+}
+
+pub fn is_admin(groups: &[String]) -> bool {
+    let admin_group = admin_group();
+
+    groups.iter().any(|g| g.eq_ignore_ascii_case(&admin_group))
+}
+
+fn require_admin(groups: &[String]) -> Result<(), Error> {
+    if is_admin(groups) {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+/// Information about a live session, as seen by an admin.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminStreamInfo {
+    /// The name of the stream.
+    name: String,
+
+    /// Who can find and watch the stream.
+    visibility: Visibility,
+
+    /// How many viewers the stream has.
+    viewers: usize,
+
+    /// When the stream was started.
+    started: i64,
+}
+
+/// Gets every live stream, including unlisted and private ones.
+#[utoipa::path(
+    get,
+    path = "/api/admin/stream",
+    responses(
+        (status = 200, description = "Listed live streams successfully", body = [AdminStreamInfo]),
+        (status = 403, description = "The user is not an admin"),
+    )
+)]
+pub async fn get_all_streams(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(svc): Extension<LiveStreamService>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            require_admin(&payload.groups)?;
+
+            let mut streams = Vec::new();
+            for stream in svc.get_all_streams().await {
+                if !stream.is_live() {
+                    continue;
+                }
+
+                streams.push(AdminStreamInfo {
+                    name: stream.name().to_string(),
+                    visibility: stream.visibility(),
+                    viewers: stream.viewer_count().await,
+                    started: stream.started().unix_timestamp(),
+                });
+            }
+
+            Ok::<_, Error>(Json(streams))
+        })
+        .await
+}
+
+/// Forcefully stops a live stream.
+///
+/// ### Remarks
+///
+/// This terminates the streamer's RTMP session. Nothing prevents them from reconnecting, see
+/// `/api/admin/account/{account}/streaming` for that.
+#[utoipa::path(
+    post,
+    path = "/api/admin/stream/{stream}/stop",
+    responses(
+        (status = 200, description = "Stopped the stream successfully"),
+        (status = 403, description = "The user is not an admin"),
+        (status = 404, description = "The stream is not live"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to stop")
+    )
+)]
+pub async fn post_stop_stream(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(svc): Extension<LiveStreamService>,
+    Path(stream): Path<String>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            require_admin(&payload.groups)?;
+
+            if !svc.force_stop_stream(&stream).await {
+                return Err(Error::NotFound);
+            }
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+/// Whether an account is allowed to stream.
+#[derive(ToSchema, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamingPermission {
+    /// Whether the account is prevented from streaming.
+    disabled: bool,
+}
+
+/// Changes whether an account is allowed to stream.
+///
+/// ### Remarks
+///
+/// Disabling streaming also stops the account's stream if it is live.
+#[utoipa::path(
+    put,
+    path = "/api/admin/account/{account}/streaming",
+    request_body = StreamingPermission,
+    responses(
+        (status = 200, description = "Changed the streaming permission successfully"),
+        (status = 403, description = "The user is not an admin"),
+        (status = 404, description = "The account does not exist"),
+    ),
+    params(
+        ("account" = String, Path, description = "The account to change")
+    )
+)]
+pub async fn put_streaming_permission(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+    Path(account): Path<String>,
+    Json(body): Json<StreamingPermission>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            require_admin(&payload.groups)?;
+
+            let name = account.clone();
+            let updated = db
+                .call(move |conn| {
+                    conn.execute(
+                        "UPDATE users SET streaming_disabled = ?1 WHERE username = ?2",
+                        params![body.disabled, name],
+                    )
+                })
+                .await
+                .context("Failed to update streaming permission")?;
+
+            if updated == 0 {
+                return Err(Error::NotFound);
+            }
+
+            if body.disabled {
+                svc.force_stop_stream(&account).await;
+            }
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+/// Revokes the stream key of an account.
+///
+/// ### Remarks
+///
+/// The key is replaced with a new one that the account owner can see on their account page.
+/// The account's stream is stopped if it is live.
+#[utoipa::path(
+    delete,
+    path = "/api/admin/account/{account}/key",
+    responses(
+        (status = 200, description = "Revoked the stream key successfully"),
+        (status = 403, description = "The user is not an admin"),
+        (status = 404, description = "The account does not exist"),
+    ),
+    params(
+        ("account" = String, Path, description = "The account whose key to revoke")
+    )
+)]
+pub async fn delete_stream_key(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+    Path(account): Path<String>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            require_admin(&payload.groups)?;
+
+            if !account::generate_stream_key(db, account.clone()).await? {
+                return Err(Error::NotFound);
+            }
+
+            svc.force_stop_stream(&account).await;
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
@@ -0,0 +1,474 @@
+use std::{env, future::Future, net::IpAddr, time::Duration};
+
+use anyhow::Context;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Extension, Form, Router,
+};
+use hmac::{Hmac, Mac};
+use idlib::{AuthorizeCookie, NoGroups, SecretKey};
+use jwt::{SignWithKey, VerifyWithKey};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::*;
+
+use crate::{access::Viewer, limit::AttemptLimiter, Connection, Error};
+
+/// The cookie holding the session of the local auth backend.
+const SESSION_COOKIE: &str = "scuffed-session";
+
+/// How long a local session lasts, in seconds.
+const SESSION_DURATION: i64 = 30 * 24 * 60 * 60;
+
+/// How many times an address can try to log in within [`LOGIN_ATTEMPT_WINDOW`].
+const LOGIN_ATTEMPTS_PER_ADDR: u32 = 10;
+
+/// How many times an account can be tried to be logged in to within [`LOGIN_ATTEMPT_WINDOW`].
+const LOGIN_ATTEMPTS_PER_USERNAME: u32 = 20;
+
+const LOGIN_ATTEMPT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+const LOGIN_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>scuffed login</title></head>
+<body>
+<form method="post" action="/auth/login">
+<input name="username" placeholder="Username" required>
+<input name="password" type="password" placeholder="Password">
+<button type="submit">Log in</button>
+</form>
+</body>
+</html>
+"#;
+
+/// Who logs users in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthBackend {
+    /// Users log in through hiveID.
+    HiveId,
+
+    /// Users log in with a username and password stored in the database.
+    Local,
+
+    /// Anyone can log in as anyone without a password. Only available in debug builds.
+    Dev,
+}
+
+impl AuthBackend {
+    /// Reads the backend from `AUTH_BACKEND`, defaulting to hiveID.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::parse(env::var("AUTH_BACKEND").ok().as_deref())
+    }
+
+    fn parse(backend: Option<&str>) -> anyhow::Result<Self> {
+        match backend {
+            None | Some("hiveid") => Ok(AuthBackend::HiveId),
+            Some("local") => Ok(AuthBackend::Local),
+            Some("dev") if cfg!(debug_assertions) => Ok(AuthBackend::Dev),
+            Some("dev") => {
+                anyhow::bail!("The dev auth backend lets anyone log in as anyone, and is only available in debug builds")
+            }
+            Some(backend) => {
+                anyhow::bail!("Unknown AUTH_BACKEND {backend:?}, expected hiveid, local or dev")
+            }
+        }
+    }
+
+    /// Gets the key used to sign sessions and tokens.
+    ///
+    /// ### Remarks
+    ///
+    /// hiveID requires the key to be configured. The other backends generate a random key when
+    /// it is missing, which logs everyone out when the server restarts.
+    pub fn secret_key(&self) -> anyhow::Result<SecretKey> {
+        match SecretKey::from_env() {
+            Ok(key) => Ok(key),
+            Err(e) if *self == AuthBackend::HiveId => {
+                Err(anyhow::anyhow!("Failed to load secret key: {e:?}"))
+            }
+            Err(_) => {
+                warn!("No secret key configured, generating a temporary one");
+
+                let mut secret_bytes = [0u8; 32];
+                StdRng::from_entropy().fill_bytes(&mut secret_bytes[..]);
+
+                Ok(SecretKey(
+                    Hmac::new_from_slice(&secret_bytes).expect("HMAC can take a key of any size"),
+                ))
+            }
+        }
+    }
+}
+
+/// The logged in user making a request.
+pub struct Caller {
+    pub name: String,
+
+    /// The groups of the user. Always empty for API tokens.
+    pub groups: Vec<String>,
+}
+
+/// Refreshes the hiveID session of cookie authenticated requests.
+pub struct MaybeSession(Option<AuthorizeCookie<NoGroups>>);
+
+impl MaybeSession {
+    pub fn none() -> Self {
+        MaybeSession(None)
+    }
+
+    pub async fn wrap_future<F>(self, f: F) -> Response
+    where
+        F: Future,
+        F::Output: IntoResponse,
+    {
+        match self.0 {
+            Some(AuthorizeCookie(_, maybe_token, ..)) => {
+                maybe_token.wrap_future(f).await.into_response()
+            }
+            None => f.await.into_response(),
+        }
+    }
+
+    pub fn wrap<R: IntoResponse>(self, f: impl FnOnce() -> R) -> Response {
+        match self.0 {
+            Some(AuthorizeCookie(_, maybe_token, ..)) => maybe_token.wrap(f).into_response(),
+            None => f().into_response(),
+        }
+    }
+}
+
+/// Authorizes a request with the session cookie of the configured [`AuthBackend`].
+pub struct Authorize(pub Caller, pub MaybeSession);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Authorize {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let backend = parts
+            .extensions
+            .get::<AuthBackend>()
+            .copied()
+            .unwrap_or(AuthBackend::HiveId);
+
+        if backend == AuthBackend::HiveId {
+            let cookie = AuthorizeCookie::<NoGroups>::from_request_parts(parts, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            let caller = Caller {
+                name: cookie.0.name.clone(),
+                groups: cookie.0.groups.clone(),
+            };
+
+            return Ok(Authorize(caller, MaybeSession(Some(cookie))));
+        }
+
+        let key = parts
+            .extensions
+            .get::<SecretKey>()
+            .ok_or_else(|| Error::InternalError(anyhow::anyhow!("Missing secret key")))
+            .map_err(IntoResponse::into_response)?;
+
+        let claims = parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == SESSION_COOKIE)
+            .and_then(|(_, token)| verify_session(key, token))
+            .ok_or_else(|| Error::Unathorized.into_response())?;
+
+        let caller = Caller {
+            name: claims.name,
+            groups: claims.groups,
+        };
+
+        Ok(Authorize(caller, MaybeSession(None)))
+    }
+}
+
+/// The claims of a session issued by the local backends.
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionClaims {
+    name: String,
+    groups: Vec<String>,
+    exp: i64,
+}
+
+fn verify_session(key: &SecretKey, token: &str) -> Option<SessionClaims> {
+    let claims: SessionClaims = match token.verify_with_key(&key.0) {
+        Ok(claims) => claims,
+        Err(e) => {
+            debug!("Rejected session: {e}");
+            return None;
+        }
+    };
+
+    if claims.exp < OffsetDateTime::now_utc().unix_timestamp() {
+        debug!("Rejected expired session");
+        return None;
+    }
+
+    Some(claims)
+}
+
+/// Routes for logging in with the local backends, used instead of hiveID's.
+pub fn local_route() -> Router {
+    Router::new()
+        .route("/login", get(get_local_login).post(post_local_login))
+        .route("/logout", post(post_local_logout))
+}
+
+/// Limits how often local passwords can be guessed, both per address and per account.
+#[derive(Clone)]
+pub struct LoginLimiter {
+    by_addr: AttemptLimiter,
+    by_username: AttemptLimiter,
+}
+
+impl LoginLimiter {
+    pub fn new() -> Self {
+        LoginLimiter {
+            by_addr: AttemptLimiter::new(LOGIN_ATTEMPTS_PER_ADDR, LOGIN_ATTEMPT_WINDOW),
+            by_username: AttemptLimiter::new(LOGIN_ATTEMPTS_PER_USERNAME, LOGIN_ATTEMPT_WINDOW),
+        }
+    }
+
+    /// Records an attempt to log in as `username` from `addr`.
+    fn check(&self, addr: Option<IpAddr>, username: &str) -> Result<(), Error> {
+        let addr = addr.map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
+
+        // both are recorded so that spreading guesses over accounts or addresses doesn't help
+        let addr_allowed = self.by_addr.try_attempt(&addr);
+        let username_allowed = self.by_username.try_attempt(&username.to_lowercase());
+
+        if addr_allowed && username_allowed {
+            Ok(())
+        } else {
+            debug!("Rate limited logging in as {username:?} from {addr}");
+            Err(Error::TooManyAttempts)
+        }
+    }
+}
+
+async fn get_local_login() -> Html<&'static str> {
+    Html(LOGIN_PAGE)
+}
+
+#[derive(Deserialize)]
+struct LocalLogin {
+    username: String,
+    password: Option<String>,
+}
+
+async fn post_local_login(
+    viewer: Viewer,
+    Extension(backend): Extension<AuthBackend>,
+    Extension(limiter): Extension<LoginLimiter>,
+    Extension(db): Extension<Connection>,
+    Extension(key): Extension<SecretKey>,
+    Form(login): Form<LocalLogin>,
+) -> Result<Response, Error> {
+    // usernames are case insensitive, so the session uses the name the account was created with
+    let (name, groups) = match backend {
+        AuthBackend::Dev => {
+            validate_username(&login.username)?;
+            warn!("Logging in as {:?} without a password", login.username);
+
+            let name = get_account_name(&db, login.username.clone())
+                .await?
+                .unwrap_or(login.username);
+
+            (name, Vec::new())
+        }
+        _ => {
+            limiter.check(viewer.addr, &login.username)?;
+
+            let password = login.password.unwrap_or_default();
+            verify_local_password(&db, login.username, password).await?
+        }
+    };
+
+    crate::create_account_if_missing(db, name.clone()).await?;
+
+    let claims = SessionClaims {
+        name,
+        groups,
+        exp: OffsetDateTime::now_utc().unix_timestamp() + SESSION_DURATION,
+    };
+    let token = claims
+        .sign_with_key(&key.0)
+        .context("Failed to sign session")?;
+
+    let cookie = format!(
+        "{SESSION_COOKIE}={token}; Path=/; Max-Age={SESSION_DURATION}; HttpOnly; Secure; SameSite=Lax"
+    );
+
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to("/")).into_response())
+}
+
+/// A `Set-Cookie` value that clears the session cookie of the local auth backends.
+pub fn logout_cookie() -> String {
+    format!("{SESSION_COOKIE}=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax")
+}
+
+async fn post_local_logout() -> impl IntoResponse {
+    (StatusCode::OK, [(header::SET_COOKIE, logout_cookie())])
+}
+
+fn validate_username(username: &str) -> Result<(), Error> {
+    let valid = !username.is_empty()
+        && username.len() <= 32
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidUsername)
+    }
+}
+
+/// Gets the name of an existing account as it was created, since usernames are case
+/// insensitive.
+async fn get_account_name(db: &Connection, username: String) -> anyhow::Result<Option<String>> {
+    db.call(move |conn| {
+        conn.query_row(
+            "SELECT username FROM users WHERE username = ?1",
+            params![username],
+            |row| row.get(0),
+        )
+        .optional()
+    })
+    .await
+    .context("Failed to query account name")
+}
+
+/// Checks the password of a local user, returning their name as stored and their groups.
+async fn verify_local_password(
+    db: &Connection,
+    username: String,
+    password: String,
+) -> Result<(String, Vec<String>), Error> {
+    let stored = db
+        .call(move |conn| {
+            conn.query_row(
+                "SELECT username, password_hash, groups FROM local_accounts WHERE username = ?1",
+                params![username],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .optional()
+        })
+        .await
+        .context("Failed to query local account")?;
+
+    let (name, hash, groups) = stored.ok_or(Error::InvalidLogin)?;
+
+    let hash = PasswordHash::new(&hash)
+        .map_err(|e| anyhow::anyhow!("Failed to parse password hash: {e}"))?;
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        return Err(Error::InvalidLogin);
+    }
+
+    let groups = groups
+        .split(',')
+        .filter(|g| !g.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    Ok((name, groups))
+}
+
+/// Creates or updates a user of the local backend.
+pub async fn set_local_password(
+    db: &Connection,
+    username: String,
+    password: String,
+    groups: Vec<String>,
+) -> anyhow::Result<()> {
+    validate_username(&username).map_err(|e| anyhow::anyhow!("{e}"))?;
+
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {e}"))?
+        .to_string();
+
+    crate::create_account_if_missing(db.clone(), username.clone()).await?;
+
+    db.call(move |conn| {
+        conn.execute(
+            "INSERT INTO local_accounts (username, password_hash, groups) VALUES (?1, ?2, ?3) \
+            ON CONFLICT(username) DO UPDATE \
+            SET password_hash = excluded.password_hash, groups = excluded.groups",
+            params![username, hash, groups.join(",")],
+        )
+    })
+    .await
+    .context("Failed to update local account")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_usernames() {
+        for username in ["alice", "Bob_2", "snail-video", &"a".repeat(32)] {
+            assert!(validate_username(username).is_ok(), "{username}");
+        }
+    }
+
+    #[test]
+    fn invalid_usernames() {
+        for username in ["", "has space", "ünicode", "../admin", &"a".repeat(33)] {
+            assert!(validate_username(username).is_err(), "{username}");
+        }
+    }
+
+    #[test]
+    fn parses_backend() {
+        assert_eq!(AuthBackend::parse(None).unwrap(), AuthBackend::HiveId);
+        assert_eq!(
+            AuthBackend::parse(Some("hiveid")).unwrap(),
+            AuthBackend::HiveId
+        );
+        assert_eq!(
+            AuthBackend::parse(Some("local")).unwrap(),
+            AuthBackend::Local
+        );
+        assert!(AuthBackend::parse(Some("ldap")).is_err());
+    }
+
+    #[test]
+    fn dev_backend_is_only_available_in_debug_builds() {
+        assert_eq!(
+            AuthBackend::parse(Some("dev")).is_ok(),
+            cfg!(debug_assertions)
+        );
+    }
+}
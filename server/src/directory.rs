@@ -0,0 +1,145 @@
+use std::convert::Infallible;
+
+use axum::{
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Extension, Router,
+};
+use futures::stream::{self, Stream};
+use serde::Serialize;
+use tracing::*;
+
+use crate::{
+    event::StreamEvent,
+    metadata::{self, StreamMetadata},
+    stream::{get_public_streams, LiveStreamInfo, LiveStreamService, Visibility},
+    Connection, Error,
+};
+
+pub fn api_route() -> Router {
+    Router::new().route("/events", get(get_stream_events))
+}
+
+/// A change to the list of public streams.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum DirectoryEvent {
+    /// A stream went live.
+    Started { stream: LiveStreamInfo },
+
+    /// A stream stopped being live.
+    Stopped { name: String },
+
+    /// A viewer started or stopped watching a stream.
+    Viewers { name: String, viewers: usize },
+
+    /// A streamer changed what their stream is about.
+    Metadata {
+        name: String,
+        metadata: StreamMetadata,
+    },
+
+    /// Events were missed, so this is the whole list of streams again.
+    Resync { streams: Vec<LiveStreamInfo> },
+}
+
+/// Gets changes to the list of public livestreams in real time through Server-Sent Events.
+///
+/// ### Messages
+///
+/// Every message is a JSON object with a `type` field:
+///
+/// * `started` when a stream goes live, with the [`LiveStreamInfo`] of the stream in `stream`.
+/// * `stopped` when a stream stops, with the name of the stream in `name`.
+/// * `viewers` when a viewer joins or leaves, with the new number of viewers in `viewers`.
+/// * `metadata` when the streamer changes the metadata, with the new metadata in `metadata`.
+/// * `resync` when the client fell too far behind and missed events, with every public stream in
+///   `streams`. The client should replace its list of streams with these.
+///
+/// Only public streams are included, the same as [`crate::stream::get_streams`].
+#[utoipa::path(
+    get,
+    path = "/api/directory/events",
+    responses(
+        (status = 200, description = "Streaming events", content_type = "text/event-stream"),
+    )
+)]
+pub async fn get_stream_events(
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = svc.subscribe();
+
+    let stream = stream::unfold((db, svc, events), |(db, svc, mut events)| async move {
+        loop {
+            let event = match events.recv_or_lagged().await? {
+                Ok(event) => directory_event(&db, &svc, event).await,
+                Err(skipped) => {
+                    debug!("Directory subscriber fell behind, skipped {skipped} events");
+                    resync_event(&db, &svc).await.map(Some)
+                }
+            };
+
+            match event {
+                Ok(Some(event)) => return Some((Ok(event), (db, svc, events))),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to describe stream event: {e:?}"),
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn directory_event(
+    db: &Connection,
+    svc: &LiveStreamService,
+    event: StreamEvent,
+) -> Result<Option<Event>, Error> {
+    let name = match &event {
+        StreamEvent::Started { stream }
+        | StreamEvent::Stopped { stream }
+        | StreamEvent::ViewerJoined { stream, .. }
+        | StreamEvent::ViewerLeft { stream, .. }
+        | StreamEvent::MetadataChanged { stream } => stream,
+        StreamEvent::IngestStats { .. } => return Ok(None),
+    };
+
+    let live_stream = match svc.get_stream(name).await {
+        Some(live_stream) if live_stream.visibility() == Visibility::Public => live_stream,
+        _ => return Ok(None),
+    };
+
+    let event = match event {
+        StreamEvent::Started { .. } => DirectoryEvent::Started {
+            stream: live_stream.describe(db).await?,
+        },
+        StreamEvent::Stopped { stream } => DirectoryEvent::Stopped { name: stream },
+        StreamEvent::ViewerJoined { stream, viewers }
+        | StreamEvent::ViewerLeft { stream, viewers } => DirectoryEvent::Viewers {
+            name: stream,
+            viewers,
+        },
+        StreamEvent::MetadataChanged { stream } => DirectoryEvent::Metadata {
+            metadata: metadata::get_metadata(db, stream.clone()).await?,
+            name: stream,
+        },
+        StreamEvent::IngestStats { .. } => return Ok(None),
+    };
+
+    Ok(Some(to_sse(event)?))
+}
+
+async fn resync_event(db: &Connection, svc: &LiveStreamService) -> Result<Event, Error> {
+    let streams = get_public_streams(db, svc).await?;
+
+    to_sse(DirectoryEvent::Resync { streams })
+}
+
+fn to_sse(event: DirectoryEvent) -> Result<Event, Error> {
+    let event = Event::default()
+        .json_data(event)
+        .map_err(|e| anyhow::anyhow!("Failed to serialize stream event: {e}"))?;
+
+    Ok(event)
+}
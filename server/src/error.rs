@@ -16,6 +16,15 @@ pub enum Error {
     #[error("Unathorized")]
     Unathorized,
 
+    #[error("Forbidden")]
+    Forbidden,
+
+    #[error("You are banned from this stream")]
+    Banned,
+
+    #[error("Stream is password protected")]
+    StreamLocked,
+
     #[error("Comment does not belong to specified image")]
     WrongImage,
 
@@ -28,6 +37,9 @@ pub enum Error {
     #[error("Invalid password")]
     InvalidPassword,
 
+    #[error("Password must not be empty")]
+    EmptyPassword,
+
     #[error("timeframe contains an invalid range")]
     InvalidTimeframe,
 
@@ -37,12 +49,33 @@ pub enum Error {
     #[error("One of the album or image keys is not valid")]
     InvalidKey,
 
+    #[error("Webhook URL must be a valid http or https URL")]
+    InvalidWebhookUrl,
+
+    #[error("Accounts can't have more than 25 API tokens")]
+    TooManyTokens,
+
+    #[error("API tokens need at least one scope")]
+    NoTokenScopes,
+
+    #[error("maxUses must be between 1 and {maximum}")]
+    InvalidInviteUses { maximum: u32 },
+
+    #[error("Streams can only be scheduled in the future")]
+    ScheduledInPast,
+
     #[error("{field} should not be longer than {maximum_length} characters")]
     TooManyCharacters {
         field: &'static str,
         maximum_length: u64,
     },
 
+    #[error("A stream can't have more than {maximum} tags")]
+    TooManyTags { maximum: usize },
+
+    #[error("Too many attempts, try again later")]
+    TooManyAttempts,
+
     #[error("Internal Server Error")]
     InternalError(#[from] anyhow::Error),
 
@@ -53,10 +86,13 @@ pub enum Error {
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         let status = match &self {
-            Error::InvalidLogin | Error::InvalidPassword | Error::Unathorized => {
-                StatusCode::UNAUTHORIZED
-            }
+            Error::InvalidLogin
+            | Error::InvalidPassword
+            | Error::Unathorized
+            | Error::StreamLocked => StatusCode::UNAUTHORIZED,
+            Error::Forbidden | Error::Banned => StatusCode::FORBIDDEN,
             Error::NotFound => StatusCode::NOT_FOUND,
+            Error::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
             Error::InternalError(e) => {
                 let err = e
                     .chain()
@@ -70,8 +106,15 @@ impl IntoResponse for Error {
             | Error::InvalidKey
             | Error::InvalidTimeframe
             | Error::InvalidUsername
+            | Error::InvalidWebhookUrl
+            | Error::EmptyPassword
+            | Error::ScheduledInPast
+            | Error::TooManyTokens
+            | Error::NoTokenScopes
+            | Error::InvalidInviteUses { .. }
             | Error::WrongImage
             | Error::TooManyCharacters { .. }
+            | Error::TooManyTags { .. }
             | Error::JsonRejection(_) => StatusCode::BAD_REQUEST,
         };
 
@@ -0,0 +1,86 @@
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+/// How many events a subscriber can fall behind before it starts missing events.
+const EVENT_CAPACITY: usize = 256;
+
+/// Something that happened to a livestream.
+#[derive(Clone, Debug)]
+pub enum StreamEvent {
+    /// A stream went live.
+    Started { stream: String },
+
+    /// A stream stopped being live.
+    Stopped { stream: String },
+
+    /// A viewer started watching a stream.
+    ViewerJoined { stream: String, viewers: usize },
+
+    /// A viewer stopped watching a stream.
+    ViewerLeft { stream: String, viewers: usize },
+
+    /// A streamer changed what their stream is about.
+    MetadataChanged { stream: String },
+
+    /// Periodic statistics about the incoming stream.
+    IngestStats { stream: String, stats: IngestStats },
+}
+
+/// Statistics about an incoming stream, measured over a short interval.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IngestStats {
+    /// The bitrate of all tracks, in bits per second.
+    pub bitrate: u64,
+
+    /// How many video frames were received per second.
+    pub framerate: f32,
+}
+
+/// Broadcasts [`StreamEvent`]s to any number of subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<StreamEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+
+        EventBus { sender }
+    }
+
+    pub fn publish(&self, event: StreamEvent) {
+        // an error only means that nobody is listening right now
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> EventSubscriber {
+        EventSubscriber(self.sender.subscribe())
+    }
+}
+
+pub struct EventSubscriber(broadcast::Receiver<StreamEvent>);
+
+impl EventSubscriber {
+    /// Waits for the next event, or returns `None` once the bus is gone.
+    pub async fn recv(&mut self) -> Option<StreamEvent> {
+        loop {
+            match self.recv_or_lagged().await? {
+                Ok(event) => return Some(event),
+                Err(skipped) => {
+                    warn!("Event subscriber fell behind, skipped {skipped} events");
+                }
+            }
+        }
+    }
+
+    /// Waits for the next event like [`EventSubscriber::recv`], but returns how many events were
+    /// skipped as an error when the subscriber fell behind, so it can catch up another way.
+    pub async fn recv_or_lagged(&mut self) -> Option<Result<StreamEvent, u64>> {
+        match self.0.recv().await {
+            Ok(event) => Some(Ok(event)),
+            Err(RecvError::Lagged(skipped)) => Some(Err(skipped)),
+            Err(RecvError::Closed) => None,
+        }
+    }
+}
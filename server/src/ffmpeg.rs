@@ -0,0 +1,34 @@
+use std::env;
+
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Child,
+};
+use tracing::*;
+
+/// The ffmpeg binary to run, from `FFMPEG_PATH`.
+pub fn ffmpeg_path() -> String {
+    env::var("FFMPEG_PATH").unwrap_or_else(|_| "ffmpeg".to_string())
+}
+
+/// Logs what a long running ffmpeg process writes to its piped stderr as it happens.
+///
+/// ### Remarks
+///
+/// An unread pipe fills up, after which ffmpeg blocks until the process is killed.
+pub fn log_stderr(child: &mut Child) {
+    let stderr = match child.stderr.take() {
+        Some(stderr) => stderr,
+        None => return,
+    };
+
+    tokio::spawn(
+        async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                warn!("ffmpeg: {line}");
+            }
+        }
+        .in_current_span(),
+    );
+}
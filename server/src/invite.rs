@@ -0,0 +1,434 @@
+use crate::{admin, auth::Authorize, Connection, Error};
+
+use anyhow::Context;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::info;
+use utoipa::ToSchema;
+
+/// How many times an invite can be used when not specified.
+const DEFAULT_INVITE_USES: u32 = 1;
+
+/// How many times an invite created by a non-admin can be used at most.
+const MAX_USER_INVITE_USES: u32 = 5;
+
+/// How many times an invite created by an admin can be used at most.
+const MAX_ADMIN_INVITE_USES: u32 = 1000;
+
+/// How long an invite is valid for when not specified, in seconds.
+const DEFAULT_INVITE_DURATION: i64 = 7 * 24 * 60 * 60;
+
+/// How long an invite created by a non-admin can be valid for, in seconds.
+const MAX_USER_INVITE_DURATION: i64 = 30 * 24 * 60 * 60;
+
+pub fn api_route() -> Router {
+    Router::new()
+        .route("/", get(get_invites).post(post_invite))
+        .route("/:code", delete(delete_invite))
+        .route("/:code/redeem", post(post_redeem_invite))
+}
+
+/// An invite that lets accounts stream.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteInfo {
+    /// The code to redeem.
+    code: String,
+
+    /// How many times the invite can be redeemed.
+    max_uses: u32,
+
+    /// How many times the invite has been redeemed.
+    uses: u32,
+
+    /// When the invite was created.
+    created_at: i64,
+
+    /// When the invite stops working.
+    expires_at: i64,
+}
+
+/// A request to create an invite.
+#[derive(ToSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewInvite {
+    /// How many times the invite can be redeemed, at most 5 for non-admins. Defaults to 1.
+    max_uses: Option<u32>,
+
+    /// How many seconds the invite is valid for, at most 30 days for non-admins. Defaults to a
+    /// week.
+    expires_in: Option<i64>,
+}
+
+impl NewInvite {
+    /// Checks the requested limits against what the creator is allowed, returning the use limit
+    /// and duration with defaults filled in.
+    fn validate(&self, is_admin: bool) -> Result<(u32, i64), Error> {
+        let (max_uses_limit, max_duration) = if is_admin {
+            (MAX_ADMIN_INVITE_USES, i64::MAX)
+        } else {
+            (MAX_USER_INVITE_USES, MAX_USER_INVITE_DURATION)
+        };
+
+        let max_uses = self.max_uses.unwrap_or(DEFAULT_INVITE_USES);
+        if max_uses == 0 || max_uses > max_uses_limit {
+            return Err(Error::InvalidInviteUses {
+                maximum: max_uses_limit,
+            });
+        }
+
+        let expires_in = self.expires_in.unwrap_or(DEFAULT_INVITE_DURATION);
+        if expires_in <= 0 || expires_in > max_duration {
+            return Err(Error::InvalidTimeframe);
+        }
+
+        Ok((max_uses, expires_in))
+    }
+}
+
+/// Whether an account is allowed to stream.
+pub async fn can_stream(db: &Connection, name: String) -> anyhow::Result<bool> {
+    let can_stream = db
+        .call(move |conn| {
+            conn.query_row(
+                "SELECT can_stream FROM users WHERE username = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+        .context("Failed to query streaming permission")?;
+
+    Ok(can_stream.unwrap_or(false))
+}
+
+fn get_new_invite_code() -> String {
+    let mut code_bytes = [0u8; 8];
+    StdRng::from_entropy().fill_bytes(&mut code_bytes[..]);
+
+    hex::encode(code_bytes)
+}
+
+/// Lists the invites created by the current user.
+#[utoipa::path(
+    get,
+    path = "/api/invite",
+    responses(
+        (status = 200, description = "Listed invites successfully", body = [InviteInfo]),
+    )
+)]
+pub async fn get_invites(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let name = payload.name;
+            let invites = db
+                .call(move |conn| {
+                    let mut stmt = conn.prepare(
+                        "SELECT code, max_uses, uses, created_at, expires_at FROM invites \
+                        WHERE created_by = ?1 \
+                        ORDER BY created_at DESC",
+                    )?;
+
+                    let invites = stmt
+                        .query_map(params![name], |row| {
+                            Ok(InviteInfo {
+                                code: row.get(0)?,
+                                max_uses: row.get(1)?,
+                                uses: row.get(2)?,
+                                created_at: row.get(3)?,
+                                expires_at: row.get(4)?,
+                            })
+                        })?
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    Ok::<_, rusqlite::Error>(invites)
+                })
+                .await
+                .context("Failed to query invites")?;
+
+            Ok::<_, Error>(Json(invites))
+        })
+        .await
+}
+
+/// Creates an invite.
+///
+/// ### Remarks
+///
+/// Only admins and accounts that are allowed to stream can create invites.
+#[utoipa::path(
+    post,
+    path = "/api/invite",
+    request_body = NewInvite,
+    responses(
+        (status = 201, description = "Created invite successfully", body = InviteInfo),
+        (status = 400, description = "The use limit or expiry is out of range"),
+        (status = 403, description = "The user is not allowed to create invites"),
+    )
+)]
+pub async fn post_invite(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Json(body): Json<NewInvite>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let is_admin = admin::is_admin(&payload.groups);
+            if !is_admin && !can_stream(&db, payload.name.clone()).await? {
+                return Err(Error::Forbidden);
+            }
+
+            let (max_uses, expires_in) = body.validate(is_admin)?;
+
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            let invite = InviteInfo {
+                code: get_new_invite_code(),
+                max_uses,
+                uses: 0,
+                created_at: now,
+                expires_at: now.saturating_add(expires_in),
+            };
+
+            let name = payload.name;
+            let code = invite.code.clone();
+            let expires_at = invite.expires_at;
+            db.call(move |conn| {
+                conn.execute(
+                    "INSERT INTO invites \
+                    (code, created_by, max_uses, uses, created_at, expires_at) \
+                    VALUES (?1, ?2, ?3, 0, ?4, ?5)",
+                    params![code, name, max_uses, now, expires_at],
+                )
+            })
+            .await
+            .context("Failed to insert invite")?;
+
+            Ok((StatusCode::CREATED, Json(invite)))
+        })
+        .await
+}
+
+/// Revokes an invite created by the current user.
+#[utoipa::path(
+    delete,
+    path = "/api/invite/{code}",
+    responses(
+        (status = 200, description = "Revoked invite successfully"),
+        (status = 404, description = "The user has no invite with the given code"),
+    ),
+    params(
+        ("code" = String, Path, description = "The invite code")
+    )
+)]
+pub async fn delete_invite(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Path(code): Path<String>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let name = payload.name;
+            let removed = db
+                .call(move |conn| {
+                    conn.execute(
+                        "DELETE FROM invites WHERE code = ?1 AND created_by = ?2",
+                        params![code, name],
+                    )
+                })
+                .await
+                .context("Failed to delete invite")?;
+
+            if removed == 0 {
+                return Err(Error::NotFound);
+            }
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+/// Redeems an invite, which lets the current user stream.
+///
+/// ### Remarks
+///
+/// Redeeming an invite when the user is already allowed to stream does not use it up.
+#[utoipa::path(
+    post,
+    path = "/api/invite/{code}/redeem",
+    responses(
+        (status = 200, description = "Redeemed invite successfully"),
+        (status = 404, description = "The invite does not exist, has expired or has been used up"),
+    ),
+    params(
+        ("code" = String, Path, description = "The invite code")
+    )
+)]
+pub async fn post_redeem_invite(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Path(code): Path<String>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            if can_stream(&db, payload.name.clone()).await? {
+                return Ok(StatusCode::OK);
+            }
+
+            let name = payload.name;
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            let inviter = db
+                .call(move |conn| redeem_invite(conn, &code, &name, now))
+                .await
+                .context("Failed to redeem invite")?;
+
+            if inviter.is_none() {
+                return Err(Error::NotFound);
+            }
+
+            Ok(StatusCode::OK)
+        })
+        .await
+}
+
+/// Uses up an invite if it is still valid and lets `name` stream, returning who created it.
+fn redeem_invite(
+    conn: &mut rusqlite::Connection,
+    code: &str,
+    name: &str,
+    now: i64,
+) -> rusqlite::Result<Option<String>> {
+    let tx = conn.transaction()?;
+
+    let inviter = tx
+        .query_row(
+            "SELECT created_by FROM invites \
+            WHERE code = ?1 AND uses < max_uses AND expires_at > ?2",
+            params![code, now],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+
+    if let Some(inviter) = &inviter {
+        tx.execute(
+            "UPDATE invites SET uses = uses + 1 WHERE code = ?1",
+            params![code],
+        )?;
+        tx.execute(
+            "UPDATE users SET can_stream = 1, invited_by = ?1 WHERE username = ?2",
+            params![inviter, name],
+        )?;
+
+        info!("{name:?} redeemed an invite from {inviter:?}");
+    }
+
+    tx.commit()?;
+
+    Ok(inviter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_invite(max_uses: Option<u32>, expires_in: Option<i64>) -> NewInvite {
+        NewInvite {
+            max_uses,
+            expires_in,
+        }
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        assert_eq!(
+            new_invite(None, None).validate(false).unwrap(),
+            (DEFAULT_INVITE_USES, DEFAULT_INVITE_DURATION)
+        );
+    }
+
+    #[test]
+    fn limits_user_invites() {
+        assert!(
+            new_invite(Some(MAX_USER_INVITE_USES), Some(MAX_USER_INVITE_DURATION))
+                .validate(false)
+                .is_ok()
+        );
+        assert!(new_invite(Some(0), None).validate(false).is_err());
+        assert!(new_invite(Some(MAX_USER_INVITE_USES + 1), None)
+            .validate(false)
+            .is_err());
+        assert!(new_invite(None, Some(0)).validate(false).is_err());
+        assert!(new_invite(None, Some(MAX_USER_INVITE_DURATION + 1))
+            .validate(false)
+            .is_err());
+    }
+
+    #[test]
+    fn limits_admin_invites() {
+        assert!(new_invite(Some(MAX_ADMIN_INVITE_USES), Some(i64::MAX))
+            .validate(true)
+            .is_ok());
+        assert!(new_invite(Some(MAX_ADMIN_INVITE_USES + 1), None)
+            .validate(true)
+            .is_err());
+    }
+
+    fn invite_db(max_uses: u32, expires_at: i64) -> rusqlite::Connection {
+        let conn = crate::test_database();
+        conn.execute_batch(
+            "INSERT INTO users (username, stream_key, can_stream) \
+            VALUES ('alice', 'a', 1), ('bob', 'b', 0), ('carol', 'c', 0);",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO invites (code, created_by, max_uses, uses, created_at, expires_at) \
+            VALUES ('code', 'alice', ?1, 0, 0, ?2)",
+            params![max_uses, expires_at],
+        )
+        .unwrap();
+
+        conn
+    }
+
+    #[test]
+    fn redeems_invite_until_used_up() {
+        let mut conn = invite_db(1, 100);
+
+        assert_eq!(
+            redeem_invite(&mut conn, "code", "bob", 0)
+                .unwrap()
+                .as_deref(),
+            Some("alice")
+        );
+        assert_eq!(redeem_invite(&mut conn, "code", "carol", 0).unwrap(), None);
+
+        let can_stream: Vec<bool> = conn
+            .prepare("SELECT can_stream FROM users ORDER BY username")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(can_stream, [true, true, false]);
+    }
+
+    #[test]
+    fn rejects_expired_and_unknown_invites() {
+        let mut conn = invite_db(1, 100);
+
+        assert_eq!(redeem_invite(&mut conn, "code", "bob", 100).unwrap(), None);
+        assert_eq!(redeem_invite(&mut conn, "nope", "bob", 0).unwrap(), None);
+    }
+}
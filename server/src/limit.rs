@@ -0,0 +1,76 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Limits how many times something can be attempted for a key within a window of time, e.g.
+/// guessing passwords from an address.
+#[derive(Clone)]
+pub struct AttemptLimiter {
+    max_attempts: u32,
+    window: Duration,
+    attempts: Arc<Mutex<HashMap<String, Attempts>>>,
+}
+
+struct Attempts {
+    /// When the first attempt of the current window was made.
+    window_start: Instant,
+    count: u32,
+}
+
+impl AttemptLimiter {
+    pub fn new(max_attempts: u32, window: Duration) -> Self {
+        AttemptLimiter {
+            max_attempts,
+            window,
+            attempts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Records an attempt for `key`, returning whether it is within the limit.
+    pub fn try_attempt(&self, key: &str) -> bool {
+        self.try_attempt_at(key, Instant::now())
+    }
+
+    fn try_attempt_at(&self, key: &str, now: Instant) -> bool {
+        let mut attempts = self.attempts.lock().unwrap();
+
+        // forget keys that have not been attempted recently so the map doesn't grow forever
+        attempts.retain(|_, a| now.duration_since(a.window_start) < self.window);
+
+        let attempts = attempts.entry(key.to_string()).or_insert(Attempts {
+            window_start: now,
+            count: 0,
+        });
+        attempts.count += 1;
+
+        attempts.count <= self.max_attempts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_attempts_per_key() {
+        let limiter = AttemptLimiter::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(limiter.try_attempt_at("a", now));
+        assert!(limiter.try_attempt_at("a", now));
+        assert!(!limiter.try_attempt_at("a", now));
+        assert!(limiter.try_attempt_at("b", now));
+    }
+
+    #[test]
+    fn allows_attempts_after_window() {
+        let limiter = AttemptLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(limiter.try_attempt_at("a", now));
+        assert!(!limiter.try_attempt_at("a", now + Duration::from_secs(59)));
+        assert!(limiter.try_attempt_at("a", now + Duration::from_secs(60)));
+    }
+}
@@ -1,7 +1,9 @@
+use std::time::Duration;
+
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Path,
+        Path, Query,
     },
     response::Response,
     routing::get,
@@ -10,49 +12,256 @@ use axum::{
 use tracing::*;
 use mediabox::{
     format::{mp4::FragmentedMp4Muxer, Movie},
-    Packet,
+    MediaKind, Packet, Track,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::{
+    sync::mpsc::Receiver,
+    time::{sleep_until, Instant},
 };
-use tokio::sync::mpsc::Receiver;
 use tracing::{Instrument, debug_span};
 
-use crate::{stream::LiveStreamService, Error};
+use crate::{
+    access::{self, Viewer},
+    event::{EventSubscriber, StreamEvent},
+    stream::{IngestedPacket, LiveStreamService, MediaType, PacketSplitter, ViewerSession},
+    Connection, Error,
+};
+
+/// How long to wait for a stream to come back after it stops before ending the connection.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The highest latency a player can report, in milliseconds.
+const MAX_REPORTED_LATENCY: f64 = 5.0 * 60.0 * 1000.0;
 
 pub fn api_route() -> Router {
     Router::new().route("/:stream", get(get_video))
 }
 
+/// A kind of track that can be watched on its own.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TrackKind {
+    Audio,
+    Video,
+}
+
+impl TrackKind {
+    pub fn matches(self, track: &Track) -> bool {
+        match self {
+            TrackKind::Audio => track.is_audio(),
+            TrackKind::Video => track.is_video(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LiveQuery {
+    /// Only send tracks of this kind.
+    tracks: Option<TrackKind>,
+
+    /// Send a transcoded rendition instead of the original stream.
+    rendition: Option<String>,
+}
+
+/// A control message sent to the player as JSON text.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ServerMessage {
+    /// The tracks of the stream.
+    Tracks { tracks: Vec<TrackInfo> },
+
+    /// The codecs changed, the next binary message is an initialization segment for them.
+    CodecChanged {
+        #[serde(rename = "contentType")]
+        content_type: String,
+    },
+
+    /// The next binary message is an initialization segment with the same codecs, and the
+    /// timestamps of the segments after it start over.
+    Resync,
+
+    /// The player is now watching another rendition, `None` being the original stream.
+    RenditionChanged { rendition: Option<String> },
+
+    /// A viewer started or stopped watching the stream.
+    Viewers { viewers: usize },
+
+    /// The next binary message starts with a video keyframe.
+    Keyframe {
+        /// When the server received the keyframe, in milliseconds since the Unix epoch.
+        #[serde(rename = "ingestedAt")]
+        ingested_at: i64,
+    },
+
+    /// The stream stopped, but the streamer might reconnect.
+    Reconnecting,
+
+    /// The stream stopped and did not come back, the connection will be closed.
+    Ended,
+
+    /// The answer to a ping.
+    Pong {
+        /// The time the player sent with the ping.
+        #[serde(rename = "clientTime")]
+        client_time: f64,
+
+        /// The time on the server when answering, in milliseconds since the Unix epoch.
+        #[serde(rename = "serverTime")]
+        server_time: i64,
+    },
+}
+
+/// A control message sent by the player as JSON text.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    /// Asks the server to answer with a pong.
+    Ping {
+        #[serde(rename = "clientTime")]
+        client_time: f64,
+    },
+
+    /// Asks the server to only send some of the tracks.
+    SelectTracks { tracks: Vec<u32> },
+
+    /// Tells the server the glass-to-glass latency the player measured, in milliseconds.
+    Latency { latency: f64 },
+
+    /// Asks the server to switch to another rendition, `None` being the original stream.
+    SelectRendition { rendition: Option<String> },
+}
+
+/// A track of a stream that the player can select.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TrackInfo {
+    id: u32,
+
+    /// Either `video` or `audio`.
+    kind: &'static str,
+
+    /// The codec of the track, as used in a MIME type.
+    codec: Option<String>,
+}
+
+impl TrackInfo {
+    fn new(track: &Track) -> Self {
+        let movie = Movie {
+            tracks: vec![track.clone()],
+            attachments: Vec::new(),
+        };
+
+        TrackInfo {
+            id: track.id,
+            kind: if track.is_video() { "video" } else { "audio" },
+            codec: movie.codec_string(),
+        }
+    }
+}
+
 /// Gets a MP4 video livestream through a websocket connection.
 ///
 /// ### Messages
 ///
-/// The WebSocket connection will only pushing video. Messages sent to the WebSocket connection
-/// will be ignored.
+/// The first message is the MIME type of the video as plain text. It is followed by binary
+/// messages which are intended to be used with the
+/// [MSE](https://developer.mozilla.org/en-US/docs/Web/API/Media_Source_Extensions_API) API,
+/// starting with an initialization segment.
+///
+/// Every other text message is a JSON object with a `type` field:
+///
+/// * `tracks` when connecting or reconnecting, listing the `id`, `kind` and `codec` of every
+///   track in `tracks`.
+/// * `codecChanged` when the codecs change, with the new MIME type in `contentType`. The next
+///   binary message is an initialization segment.
+/// * `resync` when the next binary message is an initialization segment for the same codecs,
+///   after which timestamps start over.
+/// * `renditionChanged` when the player switched to another rendition, with its name in
+///   `rendition` or `null` for the original stream. It is followed by `codecChanged` or
+///   `resync` unless the rendition has the same codec parameters.
+/// * `viewers` when a viewer joins or leaves, with the number of viewers in `viewers`.
+/// * `keyframe` right before a binary message starting with a video keyframe, with the time the
+///   server received the keyframe in `ingestedAt`, in milliseconds since the Unix epoch.
+/// * `reconnecting` when the stream stops. If the streamer reconnects within 30 seconds the
+///   video continues after a `codecChanged` or `resync` message.
+/// * `ended` when the stream did not come back, right before the connection is closed.
+/// * `pong` in answer to a ping, with the `clientTime` of the ping and the `serverTime` in
+///   milliseconds since the Unix epoch.
+///
+/// The player can send JSON text messages as well:
+///
+/// * `{"type": "ping", "clientTime": <number>}` to measure the latency to the server.
+/// * `{"type": "selectTracks", "tracks": [<id>, ...]}` to only receive some tracks. Unknown
+///   tracks are ignored, and selecting none of the tracks selects all of them.
+/// * `{"type": "latency", "latency": <number>}` to report the glass-to-glass latency in
+///   milliseconds, measured by comparing `ingestedAt` of a keyframe with the server time when
+///   the keyframe is shown. See `/api/stream/{stream}/stats`.
+/// * `{"type": "selectRendition", "rendition": <name or null>}` to switch to another rendition,
+///   or to the original stream with `null`, without reconnecting. The video continues until the
+///   next keyframe of the new rendition, where the server switches over. Unknown renditions are
+///   ignored.
 ///
-/// The messages coming from the connection will always be binary and are intended to be used with
-/// the [MSE](https://developer.mozilla.org/en-US/docs/Web/API/Media_Source_Extensions_API) API.
+/// Other messages sent to the WebSocket connection will be ignored.
+///
+/// ### Remarks
+///
+/// With `?tracks=audio` only the audio of the stream is sent, e.g. to listen on mobile data. The
+/// MIME type is `audio/mp4` in that case.
+///
+/// With `?rendition=<name>` a lower quality rendition transcoded by the server is sent instead,
+/// see the `renditions` of `/api/stream/{stream}`. If the stream reconnects before the
+/// rendition is available again, the original stream is sent.
 #[utoipa::path(
     get,
     path = "/api/live/{stream}",
     responses(
         (status = 101, description = "A livestream was found. Switching to the WebSocket protocol"),
-        (status = 404, description = "There was no active livestream for the given stream", content_type = "text/plain")
+        (status = 401, description = "The stream is private and the viewer is not logged in"),
+        (status = 403, description = "The viewer is banned, or the stream is private and the viewer is not allowed to watch it"),
+        (status = 404, description = "There was no active livestream for the given stream, it has no tracks of the requested kind, or no such rendition", content_type = "text/plain")
     ),
     params(
-        ("stream" = String, Path, description = "The stream to get the WebSocket connection from")
+        ("stream" = String, Path, description = "The stream to get the WebSocket connection from"),
+        ("tracks" = Option<String>, Query, description = "Only send `audio` or `video` tracks"),
+        ("rendition" = Option<String>, Query, description = "The transcoded rendition to send instead of the original stream, e.g. `720p`")
     )
 )]
 pub async fn get_video(
     Path(stream): Path<String>,
+    Query(query): Query<LiveQuery>,
     ws: WebSocketUpgrade,
+    viewer: Viewer,
+    Extension(db): Extension<Connection>,
     Extension(svc): Extension<LiveStreamService>,
 ) -> Result<Response, Error> {
     debug!("Received video request for {stream}");
 
+    let live_stream = svc.get_stream(&stream).await.ok_or(Error::NotFound)?;
+    access::authorize_viewer(&db, live_stream.name(), live_stream.visibility(), &viewer).await?;
+
     let mut splitter = svc
         .get_splitter_for_stream(&stream)
         .await
         .ok_or(Error::NotFound)?;
-    let (movie, receiver) = splitter.attach().await;
+    let session = ViewerSession::new(&viewer);
+    let (movie, receiver) = splitter
+        .attach(session.clone(), query.rendition.as_deref())
+        .await
+        .ok_or(Error::NotFound)?;
+    let events = svc.subscribe();
+
+    if let Some(kind) = query.tracks {
+        if !movie.tracks.iter().any(|track| kind.matches(track)) {
+            return Err(Error::NotFound);
+        }
+    }
+
+    svc.publish(StreamEvent::ViewerJoined {
+        stream: stream.clone(),
+        viewers: splitter.viewer_count().await,
+    });
 
     Ok(ws.on_upgrade(move |socket| {
         let span = debug_span!(
@@ -60,72 +269,545 @@ pub async fn get_video(
             stream = %stream,
         );
 
-        websocket_video(socket, movie, receiver).instrument(span)
+        async move {
+            let mut connection = LiveConnection {
+                socket,
+                db,
+                svc,
+                stream,
+                viewer,
+                session,
+                splitter,
+                receiver,
+                events,
+                movie,
+                kind: query.tracks,
+                rendition: query.rendition,
+                selection: None,
+                output: None,
+                switch: None,
+                reconnect_deadline: None,
+            };
+
+            if let Err(e) = connection.run().await {
+                warn!("Error while sending video over websocket: {e}");
+            }
+
+            let LiveConnection {
+                svc,
+                stream,
+                splitter,
+                receiver,
+                ..
+            } = connection;
+            drop(receiver);
+
+            svc.publish(StreamEvent::ViewerLeft {
+                viewers: splitter.viewer_count().await,
+                stream,
+            });
+        }
+        .instrument(span)
     }))
 }
 
-async fn websocket_video(socket: WebSocket, movie: Movie, receiver: Receiver<Packet>) {
-    if let Err(e) = websocket_video_impl(socket, movie, receiver).await {
-        warn!("Error while sending video over websocket: {e}");
-    }
+/// The tracks being sent to a player.
+struct Output {
+    tracks: Vec<Track>,
+    content_type: String,
+    fragger: FragmentedMp4Muxer,
+
+    /// Whether the initialization segment has been sent.
+    synced: bool,
 }
 
-async fn wait_for_sync_frame(recv: &mut Receiver<Packet>) -> anyhow::Result<Packet> {
-    loop {
-        let pkt = recv
-            .recv()
-            .await
-            .ok_or(anyhow::anyhow!("Packet channel closed"))?;
+impl Output {
+    fn new(
+        movie: &Movie,
+        kind: Option<TrackKind>,
+        selection: Option<&[u32]>,
+    ) -> anyhow::Result<Self> {
+        let available = movie
+            .tracks
+            .iter()
+            .filter(|track| kind.is_none_or(|kind| kind.matches(track)))
+            .cloned()
+            .collect::<Vec<_>>();
+        if available.is_empty() {
+            anyhow::bail!("Stream has no {kind:?} tracks");
+        }
 
-        if pkt.track.is_video() && pkt.key {
-            return Ok(pkt);
+        let mut tracks = available
+            .iter()
+            .filter(|track| selection.is_none_or(|selection| selection.contains(&track.id)))
+            .cloned()
+            .collect::<Vec<_>>();
+        if tracks.is_empty() {
+            tracks = available;
+        }
+
+        let movie = Movie {
+            tracks: tracks.clone(),
+            attachments: Vec::new(),
+        };
+        let codec_string = movie
+            .codec_string()
+            .ok_or(anyhow::anyhow!("Failed to create codec string"))?;
+        let content_type = format!(
+            "{}; codecs=\"{}\"",
+            MediaType::of(&movie).mp4_mime_type(),
+            codec_string
+        );
+
+        debug!("Content type: {content_type}");
+
+        Ok(Output {
+            fragger: FragmentedMp4Muxer::with_streams(&tracks),
+            tracks,
+            content_type,
+            synced: false,
+        })
+    }
+
+    /// Whether the video can start at the packet.
+    fn is_sync_point(&self, pkt: &Packet) -> bool {
+        if self.tracks.iter().any(Track::is_video) {
+            pkt.track.is_video() && pkt.key
+        } else {
+            true
         }
     }
+
+    /// Whether packets for `other` can be written after the initialization segment of this
+    /// output.
+    fn has_same_parameters(&self, other: &Output) -> bool {
+        fn dimensions(track: &Track) -> Option<(u32, u32)> {
+            match &track.info.kind {
+                MediaKind::Video(video) => Some((video.width, video.height)),
+                _ => None,
+            }
+        }
+
+        self.content_type == other.content_type
+            && self.tracks.len() == other.tracks.len()
+            && self.tracks.iter().zip(&other.tracks).all(|(a, b)| {
+                a.id == b.id && a.timebase == b.timebase && dimensions(a) == dimensions(b)
+            })
+    }
 }
 
-async fn websocket_video_impl(
-    mut socket: WebSocket,
+/// A rendition the player asked to switch to.
+struct PendingSwitch {
+    /// The name of the rendition, or `None` for the original stream.
+    rendition: Option<String>,
     movie: Movie,
-    mut receiver: Receiver<Packet>,
-) -> anyhow::Result<()> {
-    let mut fragger = FragmentedMp4Muxer::with_streams(&movie.tracks);
+    output: Output,
+}
 
-    let first_frame = wait_for_sync_frame(&mut receiver).await?;
+/// A player watching a stream through a websocket connection.
+struct LiveConnection {
+    socket: WebSocket,
+    db: Connection,
+    svc: LiveStreamService,
+    stream: String,
+    viewer: Viewer,
+    session: ViewerSession,
+    splitter: PacketSplitter,
+    receiver: Receiver<IngestedPacket>,
+    events: EventSubscriber,
+    movie: Movie,
 
-    let codec_string = movie
-        .codec_string()
-        .ok_or(anyhow::anyhow!("Failed to create codec string"))?;
-    let content_type = format!("video/mp4; codecs=\"{}\"", codec_string);
+    /// The kind of tracks the player asked for when connecting, or `None` for all kinds.
+    kind: Option<TrackKind>,
 
-    debug!("Video content type: {content_type}");
+    /// The rendition the player is watching, or `None` for the original stream.
+    rendition: Option<String>,
 
-    socket.send(Message::Text(content_type)).await?;
+    /// The IDs of the tracks the player asked for, or `None` for all tracks.
+    selection: Option<Vec<u32>>,
+    output: Option<Output>,
 
-    socket
-        .send(Message::Binary(
-            fragger.initialization_segment()?.to_slice().into_owned(),
-        ))
-        .await?;
+    /// The rendition to switch to at its next sync point.
+    switch: Option<PendingSwitch>,
+
+    /// When to give up waiting for a stopped stream to come back.
+    reconnect_deadline: Option<Instant>,
+}
 
-    socket
-        .send(Message::Binary(
-            fragger
-                .write_media_segment(first_frame)?
-                .to_slice()
-                .into_owned(),
-        ))
+impl LiveConnection {
+    async fn run(&mut self) -> anyhow::Result<()> {
+        let output = Output::new(&self.movie, self.kind, None)?;
+
+        // the first message is the plain MIME type, control messages follow as JSON
+        self.socket
+            .send(Message::Text(output.content_type.clone()))
+            .await?;
+        self.output = Some(output);
+        self.send_tracks().await?;
+
+        loop {
+            let deadline = self.reconnect_deadline;
+
+            tokio::select! {
+                pkt = self.receiver.recv() => match pkt {
+                    Some(pkt) => self.write_packet(pkt).await?,
+                    // the viewer was kicked or fell too far behind
+                    None => return Ok(()),
+                },
+                message = self.socket.recv() => match message {
+                    Some(Ok(Message::Text(text))) => self.handle_message(&text).await?,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                    Some(Ok(_)) => {}
+                },
+                event = self.events.recv() => match event {
+                    Some(event) => self.handle_event(event).await?,
+                    None => return Ok(()),
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    debug!("Stream did not come back");
+
+                    self.send(ServerMessage::Ended).await?;
+                    self.socket.send(Message::Close(None)).await?;
+
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn write_packet(&mut self, pkt: IngestedPacket) -> anyhow::Result<()> {
+        let IngestedPacket {
+            packet: pkt,
+            ingested_at,
+            rendition,
+        } = pkt;
+
+        let is_switch_point = self.switch.as_ref().is_some_and(|switch| {
+            switch.rendition.as_deref() == rendition.as_deref()
+                && switch.output.tracks.iter().any(|t| t.id == pkt.track.id)
+                && switch.output.is_sync_point(&pkt)
+        });
+        if is_switch_point {
+            self.finish_switch().await?;
+        }
+
+        // packets of the rendition being switched to or from are sent until the switch
+        if rendition.as_deref() != self.rendition.as_deref() {
+            return Ok(());
+        }
+
+        let output = match &mut self.output {
+            Some(output) if output.tracks.iter().any(|t| t.id == pkt.track.id) => output,
+            _ => return Ok(()),
+        };
+
+        if !output.synced {
+            if !output.is_sync_point(&pkt) {
+                return Ok(());
+            }
+
+            output.synced = true;
+            let init = output.fragger.initialization_segment()?;
+            self.socket
+                .send(Message::Binary(init.to_slice().into_owned()))
+                .await?;
+        }
+
+        if pkt.track.is_video() && pkt.key {
+            let ingested_at = ingested_at.unix_timestamp_nanos() / 1_000_000;
+            let message = ServerMessage::Keyframe {
+                ingested_at: ingested_at as i64,
+            };
+
+            self.socket
+                .send(Message::Text(serde_json::to_string(&message)?))
+                .await?;
+        }
+
+        let segment = output.fragger.write_media_segment(pkt)?;
+        self.socket
+            .send(Message::Binary(segment.to_slice().into_owned()))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn handle_message(&mut self, text: &str) -> anyhow::Result<()> {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Ignoring invalid control message: {e}");
+                return Ok(());
+            }
+        };
+
+        match message {
+            ClientMessage::Ping { client_time } => {
+                let server_time = OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000;
+
+                self.send(ServerMessage::Pong {
+                    client_time,
+                    server_time: server_time as i64,
+                })
+                .await
+            }
+            ClientMessage::SelectTracks { tracks } => {
+                debug!("Selecting tracks {tracks:?}");
+
+                self.selection = Some(tracks);
+                if let Some(switch) = &mut self.switch {
+                    switch.output =
+                        Output::new(&switch.movie, self.kind, self.selection.as_deref())?;
+                }
+
+                self.restart_output().await
+            }
+            ClientMessage::Latency { latency } => {
+                if (0.0..=MAX_REPORTED_LATENCY).contains(&latency) {
+                    self.splitter
+                        .report_latency(&self.session.id, latency)
+                        .await;
+                }
+
+                Ok(())
+            }
+            ClientMessage::SelectRendition { rendition } => self.select_rendition(rendition).await,
+        }
+    }
+
+    /// Starts switching to another rendition, which happens at its next keyframe.
+    async fn select_rendition(&mut self, rendition: Option<String>) -> anyhow::Result<()> {
+        if rendition == self.rendition && self.switch.is_none() {
+            return Ok(());
+        }
+
+        let movie = match self.splitter.rendition_movie(rendition.as_deref()).await {
+            Some(movie) => movie,
+            None => {
+                debug!("Ignoring switch to unknown rendition {rendition:?}");
+                return Ok(());
+            }
+        };
+
+        let output = match Output::new(&movie, self.kind, self.selection.as_deref()) {
+            Ok(output) => output,
+            Err(e) => {
+                debug!("Ignoring switch to rendition {rendition:?}: {e}");
+                return Ok(());
+            }
+        };
+
+        debug!("Switching to rendition {rendition:?}");
+
+        self.splitter
+            .start_switch(&self.session.id, rendition.as_deref())
+            .await;
+        self.switch = Some(PendingSwitch {
+            rendition,
+            movie,
+            output,
+        });
+
+        Ok(())
+    }
+
+    /// Continues with the rendition being switched to. A new initialization segment is only
+    /// sent if its codec parameters differ from the current ones.
+    async fn finish_switch(&mut self) -> anyhow::Result<()> {
+        let switch = match self.switch.take() {
+            Some(switch) => switch,
+            None => return Ok(()),
+        };
+
+        self.splitter.finish_switch(&self.session.id).await;
+        self.rendition = switch.rendition.clone();
+        self.movie = switch.movie;
+
+        self.send(ServerMessage::RenditionChanged {
+            rendition: switch.rendition,
+        })
         .await?;
 
-    loop {
-        let pkt = receiver
-            .recv()
+        let message = match &self.output {
+            Some(previous) if previous.has_same_parameters(&switch.output) => return Ok(()),
+            Some(previous) if previous.content_type == switch.output.content_type => {
+                ServerMessage::Resync
+            }
+            _ => ServerMessage::CodecChanged {
+                content_type: switch.output.content_type.clone(),
+            },
+        };
+        self.output = Some(switch.output);
+
+        self.send_tracks().await?;
+        self.send(message).await
+    }
+
+    async fn handle_event(&mut self, event: StreamEvent) -> anyhow::Result<()> {
+        match event {
+            StreamEvent::ViewerJoined { stream, viewers }
+            | StreamEvent::ViewerLeft { stream, viewers }
+                if stream.eq_ignore_ascii_case(&self.stream) =>
+            {
+                self.send(ServerMessage::Viewers { viewers }).await
+            }
+            StreamEvent::Stopped { stream } if stream.eq_ignore_ascii_case(&self.stream) => {
+                self.reconnect_deadline = Some(Instant::now() + RECONNECT_TIMEOUT);
+
+                self.send(ServerMessage::Reconnecting).await
+            }
+            StreamEvent::Started { stream }
+                if stream.eq_ignore_ascii_case(&self.stream)
+                    && self.reconnect_deadline.is_some() =>
+            {
+                self.reattach().await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Continues with the new session of a stream that stopped.
+    async fn reattach(&mut self) -> anyhow::Result<()> {
+        let live_stream = match self.svc.get_stream(&self.stream).await {
+            Some(live_stream) => live_stream,
+            None => return Ok(()),
+        };
+
+        // the streamer might have changed who can watch
+        if let Err(e) = access::authorize_viewer(
+            &self.db,
+            live_stream.name(),
+            live_stream.visibility(),
+            &self.viewer,
+        )
+        .await
+        {
+            debug!("Viewer can no longer watch the stream: {e}");
+            return Ok(());
+        }
+
+        let mut splitter = match self.svc.get_splitter_for_stream(&self.stream).await {
+            Some(splitter) => splitter,
+            None => return Ok(()),
+        };
+
+        // the rendition is not available until the transcoder has started
+        let attached = match splitter
+            .attach(self.session.clone(), self.rendition.as_deref())
             .await
-            .ok_or(anyhow::anyhow!("Packet channel closed"))?;
+        {
+            Some(attached) => attached,
+            None => {
+                self.rendition = None;
+                splitter
+                    .attach(self.session.clone(), None)
+                    .await
+                    .ok_or(anyhow::anyhow!("Failed to attach to stream"))?
+            }
+        };
+        let (movie, receiver) = attached;
+
+        self.svc.publish(StreamEvent::ViewerJoined {
+            stream: self.stream.clone(),
+            viewers: splitter.viewer_count().await,
+        });
+
+        self.splitter = splitter;
+        self.receiver = receiver;
+        self.movie = movie;
+        self.switch = None;
+        self.reconnect_deadline = None;
+
+        self.send_tracks().await?;
+        self.restart_output().await
+    }
+
+    /// Starts over with a new initialization segment, e.g. after selecting other tracks.
+    async fn restart_output(&mut self) -> anyhow::Result<()> {
+        let output = Output::new(&self.movie, self.kind, self.selection.as_deref())?;
 
-        socket
-            .send(Message::Binary(
-                fragger.write_media_segment(pkt)?.to_slice().into_owned(),
-            ))
+        let message = match &self.output {
+            Some(previous) if previous.content_type == output.content_type => ServerMessage::Resync,
+            _ => ServerMessage::CodecChanged {
+                content_type: output.content_type.clone(),
+            },
+        };
+        self.output = Some(output);
+
+        self.send(message).await
+    }
+
+    async fn send_tracks(&mut self) -> anyhow::Result<()> {
+        let tracks = self
+            .movie
+            .tracks
+            .iter()
+            .filter(|track| self.kind.is_none_or(|kind| kind.matches(track)))
+            .map(TrackInfo::new)
+            .collect();
+
+        self.send(ServerMessage::Tracks { tracks }).await
+    }
+
+    async fn send(&mut self, message: ServerMessage) -> anyhow::Result<()> {
+        self.socket
+            .send(Message::Text(serde_json::to_string(&message)?))
             .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn server_messages_match_docs() {
+        let message = |m: ServerMessage| serde_json::to_value(m).unwrap();
+
+        assert_eq!(
+            message(ServerMessage::CodecChanged {
+                content_type: "video/mp4".into()
+            }),
+            json!({"type": "codecChanged", "contentType": "video/mp4"})
+        );
+        assert_eq!(message(ServerMessage::Resync), json!({"type": "resync"}));
+        assert_eq!(
+            message(ServerMessage::RenditionChanged { rendition: None }),
+            json!({"type": "renditionChanged", "rendition": null})
+        );
+        assert_eq!(
+            message(ServerMessage::Keyframe { ingested_at: 1 }),
+            json!({"type": "keyframe", "ingestedAt": 1})
+        );
+        assert_eq!(
+            message(ServerMessage::Pong {
+                client_time: 1.5,
+                server_time: 2
+            }),
+            json!({"type": "pong", "clientTime": 1.5, "serverTime": 2})
+        );
+    }
+
+    #[test]
+    fn parses_client_messages() {
+        let message = |m: &str| serde_json::from_str::<ClientMessage>(m).unwrap();
+
+        assert!(matches!(
+            message(r#"{"type": "ping", "clientTime": 1.5}"#),
+            ClientMessage::Ping { client_time } if client_time == 1.5
+        ));
+        assert!(matches!(
+            message(r#"{"type": "selectTracks", "tracks": [1, 2]}"#),
+            ClientMessage::SelectTracks { tracks } if tracks == [1, 2]
+        ));
+        assert!(matches!(
+            message(r#"{"type": "selectRendition", "rendition": null}"#),
+            ClientMessage::SelectRendition { rendition: None }
+        ));
+        assert!(serde_json::from_str::<ClientMessage>(r#"{"type": "unknown"}"#).is_err());
     }
 }
@@ -10,30 +10,63 @@ use axum::response::Response;
 use axum::routing::get;
 use axum::{http::StatusCode, response::IntoResponse, Extension, Router};
 use futures::FutureExt;
-use idlib::{AuthCallback, IdpClient, SecretKey, Variables};
+use idlib::{AuthCallback, IdpClient, Variables};
 
 use tracing::*;
 use rusqlite::{params, OptionalExtension};
 use rusqlite_migration::{Migrations, M};
 
+mod access;
 mod account;
+mod admin;
+mod auth;
+mod directory;
 mod error;
+mod event;
+mod ffmpeg;
+mod invite;
+mod limit;
 mod live;
 mod logging;
+mod metadata;
 mod notification;
+mod presence;
+mod schedule;
 mod stream;
+mod thumbnail;
+mod token;
+mod transcode;
+mod webhook;
 
 pub use error::Error;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+use crate::auth::AuthBackend;
 use crate::notification::WebPushKeys;
 
 pub type Connection = tokio_rusqlite::Connection;
 
-const MIGRATIONS: [M; 1] = [M::up(include_str!("../migrations/0001_initial.sql"))];
-
-async fn create_account_if_missing(db: Connection, name: String) -> anyhow::Result<()> {
+const MIGRATIONS: [M; 16] = [
+    M::up(include_str!("../migrations/0001_initial.sql")),
+    M::up(include_str!("../migrations/0002_webhooks.sql")),
+    M::up(include_str!("../migrations/0003_web_push_keys.sql")),
+    M::up(include_str!("../migrations/0004_scheduled_streams.sql")),
+    M::up(include_str!("../migrations/0005_stream_access.sql")),
+    M::up(include_str!("../migrations/0006_share_secret.sql")),
+    M::up(include_str!("../migrations/0007_stream_password.sql")),
+    M::up(include_str!("../migrations/0008_streaming_disabled.sql")),
+    M::up(include_str!("../migrations/0009_viewer_bans.sql")),
+    M::up(include_str!("../migrations/0010_share_presence.sql")),
+    M::up(include_str!("../migrations/0011_api_tokens.sql")),
+    M::up(include_str!("../migrations/0012_local_accounts.sql")),
+    M::up(include_str!("../migrations/0013_invites.sql")),
+    M::up(include_str!("../migrations/0014_stream_metadata.sql")),
+    M::up(include_str!("../migrations/0015_foreign_keys.sql")),
+    M::up(include_str!("../migrations/0016_unique_stream_keys.sql")),
+];
+
+pub async fn create_account_if_missing(db: Connection, name: String) -> anyhow::Result<()> {
     db.call(move |conn| {
         if let None = conn
             .query_row(
@@ -44,7 +77,7 @@ async fn create_account_if_missing(db: Connection, name: String) -> anyhow::Resu
             .optional()
             .context("Failed to query users")?
         {
-            let stream_key = "test123";
+            let stream_key = account::get_new_stream_key();
 
             conn.execute(
                 "INSERT INTO users (username, stream_key) VALUES (?1, ?2)",
@@ -69,26 +102,100 @@ pub async fn api_route(
     svc: stream::LiveStreamService,
     web_keys: Option<WebPushKeys>,
 ) -> Router {
-    let secret_key = SecretKey::from_env().unwrap();
-    let variables = Variables::from_env().unwrap();
-
-    let client = IdpClient::default();
+    let backend = AuthBackend::from_env().unwrap();
+    let secret_key = backend.secret_key().unwrap();
 
     #[derive(OpenApi)]
     #[openapi(
         paths(
             stream::get_streams,
+            stream::get_stream,
+            directory::get_stream_events,
             stream::get_preview,
+            thumbnail::get_thumbnail,
+            stream::get_stats,
+            stream::post_unlock_stream,
+            stream::get_viewers,
+            stream::post_kick_viewer,
+            stream::post_ban_viewer,
+            stream::get_bans,
+            stream::delete_ban,
+            presence::get_presence_updates,
             live::get_video,
             account::get_account,
+            account::delete_account,
+            account::get_account_export,
             account::get_login,
             account::post_generate_stream_key,
+            account::get_stream_metadata,
+            account::put_stream_metadata,
+            account::get_stream_access,
+            account::put_stream_access,
+            account::post_share_link,
+            account::delete_share_links,
+            account::put_stream_password,
+            account::get_presence_settings,
+            account::put_presence_settings,
+            token::get_tokens,
+            token::post_token,
+            token::delete_token,
+            admin::get_all_streams,
+            admin::post_stop_stream,
+            admin::put_streaming_permission,
+            admin::delete_stream_key,
+            invite::get_invites,
+            invite::post_invite,
+            invite::delete_invite,
+            invite::post_redeem_invite,
             notification::get_public_key,
             notification::get_notification_settings,
             notification::post_notification_subscription,
             notification::delete_notification_subscription,
+            notification::post_test_notification,
+            notification::get_notification_preferences,
+            notification::put_notification_preferences,
+            schedule::get_schedule,
+            schedule::post_scheduled_stream,
+            schedule::delete_scheduled_stream,
+            webhook::get_webhooks,
+            webhook::post_webhook,
+            webhook::delete_webhook,
+            webhook::get_webhook_deliveries,
         ),
-        components(schemas(stream::LiveStreamInfo, account::AccountInfo))
+        components(schemas(
+            stream::LiveStreamInfo,
+            stream::LiveStreamDetails,
+            stream::StreamStats,
+            account::AccountInfo,
+            metadata::StreamMetadata,
+            account::StreamAccessInfo,
+            account::NewShareLink,
+            account::ShareLink,
+            account::StreamPassword,
+            account::PresenceSettings,
+            token::TokenScope,
+            token::ApiTokenInfo,
+            token::NewApiToken,
+            token::CreatedApiToken,
+            stream::UnlockRequest,
+            stream::Visibility,
+            stream::MediaType,
+            stream::ViewerInfo,
+            stream::NewViewerBan,
+            stream::ViewerBanInfo,
+            presence::ViewerPresence,
+            admin::AdminStreamInfo,
+            admin::StreamingPermission,
+            invite::InviteInfo,
+            invite::NewInvite,
+            notification::NotificationPreferences,
+            schedule::ScheduledStreamInfo,
+            schedule::NewScheduledStream,
+            webhook::WebhookInfo,
+            webhook::NewWebhook,
+            webhook::WebhookDelivery,
+            webhook::WebhookEventKind,
+        ))
     )]
     struct ApiDoc;
 
@@ -97,33 +204,53 @@ pub async fn api_route(
         .merge(SwaggerUi::new("/swagger").url("/api-doc/openapi.json", ApiDoc::openapi()))
         .route("/api/health", get(health))
         .nest("/api/stream/", stream::api_route())
+        .nest("/api/directory/", directory::api_route())
         .nest("/api/live/", live::api_route())
         .nest("/api/account/", account::api_route())
+        .nest("/api/admin/", admin::api_route())
+        .nest("/api/invite/", invite::api_route())
         .nest("/api/notification/", notification::api_route())
-        .nest(
-            "/auth",
-            idlib::api_route(
-                client,
-                Some(AuthCallback(Arc::new(Box::new(move |name| {
-                    let db = a.clone();
-
-                    async move {
-                        create_account_if_missing(db, name).await?;
-
-                        Ok(())
-                    }
-                    .boxed()
-                })))),
-            ),
-        )
-        .layer(Extension(IdpClient::default()));
+        .nest("/api/schedule/", schedule::api_route())
+        .nest("/api/webhook/", webhook::api_route());
+
+    if backend == AuthBackend::HiveId {
+        let variables = Variables::from_env().unwrap();
+        let client = IdpClient::default();
+
+        router = router
+            .nest(
+                "/auth",
+                idlib::api_route(
+                    client,
+                    Some(AuthCallback(Arc::new(Box::new(move |name| {
+                        let db = a.clone();
+
+                        async move {
+                            create_account_if_missing(db, name).await?;
+
+                            Ok(())
+                        }
+                        .boxed()
+                    })))),
+                ),
+            )
+            .layer(Extension(IdpClient::default()))
+            .layer(Extension(Arc::new(variables)));
+    } else {
+        warn!("Using the {backend:?} auth backend instead of hiveID");
+
+        router = router
+            .nest("/auth", auth::local_route())
+            .layer(Extension(auth::LoginLimiter::new()));
+    }
 
     router = router
+        .layer(Extension(backend))
         .layer(Extension(db))
         .layer(Extension(svc))
+        .layer(Extension(access::UnlockLimiter::new()))
         .layer(Extension(secret_key))
-        .layer(Extension(Arc::new(web_keys)))
-        .layer(Extension(Arc::new(variables)));
+        .layer(Extension(Arc::new(web_keys)));
 
     router
 }
@@ -155,11 +282,43 @@ async fn get_stream_key(db: Connection, name: String) -> anyhow::Result<String>
     Ok(key)
 }
 
-async fn run() {
+/// Creates an in-memory database with the latest migrations applied.
+#[cfg(test)]
+fn test_database() -> rusqlite::Connection {
+    let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    Migrations::new(MIGRATIONS.to_vec())
+        .to_latest(&mut conn)
+        .unwrap();
+    conn.pragma_update(None, "foreign_keys", true).unwrap();
+
+    conn
+}
+
+async fn open_database() -> Connection {
     let db_path: PathBuf = env::var("DB_PATH").expect("DB_PATH not set").into();
 
-    let web_push_keys = WebPushKeys::from_env();
+    let conn = tokio_rusqlite::Connection::open(&db_path)
+        .await
+        .expect("Failed to open database");
+
+    // apply latest migrations
+    conn.call(|mut c| {
+        let migrations = Migrations::new(MIGRATIONS.to_vec());
+        migrations
+            .to_latest(&mut c)
+            .expect("Failed to apply migrations");
+
+        // SQLite only enforces foreign keys, and cascades deletes, when asked to on every
+        // connection
+        c.pragma_update(None, "foreign_keys", true)
+            .expect("Failed to enable foreign keys");
+    })
+    .await;
+
+    conn
+}
 
+async fn run() {
     let rtmp_bind_addr: SocketAddr = env::var("RTMP_BIND_ADDRESS")
         .expect("RTMP_ADDRESS not set")
         .parse()
@@ -172,27 +331,34 @@ async fn run() {
     info!("Listening for RTMP requests on {rtmp_bind_addr:?}");
     info!("Listening for HTTP requests on {http_bind_addr:?}");
 
-    let conn = tokio_rusqlite::Connection::open(&db_path)
-        .await
-        .expect("Failed to open database");
+    let conn = open_database().await;
 
-    // apply latest migrations
-    conn.call(|mut c| {
-        let migrations = Migrations::new(MIGRATIONS.to_vec());
-        migrations
-            .to_latest(&mut c)
-            .expect("Failed to apply migrations");
-    })
-    .await;
+    let web_push_keys = match WebPushKeys::load(&conn).await {
+        Ok(keys) => Some(keys),
+        Err(e) => {
+            error!("Web push notifications are disabled: {e:?}");
+            None
+        }
+    };
 
     let svc = stream::LiveStreamService::new();
 
+    tokio::spawn(webhook::Webhooks::new(conn.clone(), svc.clone()).listen(svc.subscribe()));
+    if let Some(keys) = web_push_keys.clone() {
+        tokio::spawn(notification::listen(
+            conn.clone(),
+            svc.clone(),
+            keys.clone(),
+            svc.subscribe(),
+        ));
+        tokio::spawn(notification::send_schedule_reminders(conn.clone(), keys));
+    }
+
     {
         let db = conn.clone();
         let svc = svc.clone();
-        let keys = web_push_keys.clone();
         tokio::spawn(async move {
-            if let Err(e) = stream::listen(db, keys, svc, rtmp_bind_addr).await {
+            if let Err(e) = stream::listen(db, svc, rtmp_bind_addr).await {
                 error!("{}", e);
             }
         });
@@ -202,11 +368,52 @@ async fn run() {
 
     axum::Server::try_bind(&http_bind_addr)
         .expect("Failed to bind server")
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
 
+async fn rotate_vapid_keys() {
+    let conn = open_database().await;
+
+    let keys = WebPushKeys::rotate(&conn)
+        .await
+        .expect("Failed to rotate VAPID keys");
+
+    info!("Generated new VAPID keys, public key: {}", keys.public_key);
+    warn!("Existing notification subscriptions are tied to the old keys and must re-subscribe");
+
+    if WebPushKeys::from_env().is_some() {
+        warn!("WEB_PUSH_PUBLIC_KEY_B64 and WEB_PUSH_PRIVATE_KEY_B64 are set and take precedence over the generated keys");
+    }
+}
+
+/// Creates or updates a user of the `local` auth backend.
+///
+/// Usage: `scuffed set-password <username> <password> [groups]`, where groups are separated
+/// by commas.
+async fn set_local_password() {
+    let (username, password) = match (env::args().nth(2), env::args().nth(3)) {
+        (Some(username), Some(password)) => (username, password),
+        _ => {
+            error!("Usage: scuffed set-password <username> <password> [groups]");
+            return;
+        }
+    };
+    let groups = env::args()
+        .nth(4)
+        .map(|groups| groups.split(',').map(str::to_string).collect())
+        .unwrap_or_default();
+
+    let conn = open_database().await;
+
+    auth::set_local_password(&conn, username.clone(), password, groups)
+        .await
+        .expect("Failed to set password");
+
+    info!("Set the password of {username:?}");
+}
+
 async fn handle_error(_err: std::io::Error) -> impl IntoResponse {
     (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong...")
 }
@@ -215,9 +422,20 @@ fn main() {
     dotenv::dotenv().ok();
     logging::init();
 
+    let command = env::args().nth(1);
+
     tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .unwrap()
-        .block_on(async { run().await })
+        .block_on(async {
+            match command.as_deref() {
+                None => run().await,
+                Some("rotate-vapid-keys") => rotate_vapid_keys().await,
+                Some("set-password") => set_local_password().await,
+                Some(command) => error!(
+                    "Unknown command {command:?}, expected rotate-vapid-keys or set-password"
+                ),
+            }
+        })
 }
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{Connection, Error};
+
+const MAX_TITLE_LENGTH: usize = 100;
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+const MAX_CATEGORY_LENGTH: usize = 50;
+const MAX_TAG_LENGTH: usize = 30;
+const MAX_TAGS: usize = 10;
+
+/// What a streamer is streaming.
+#[derive(ToSchema, Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamMetadata {
+    /// The title of the stream.
+    pub title: Option<String>,
+
+    /// A longer description of the stream.
+    pub description: Option<String>,
+
+    /// What kind of content is being streamed, e.g. the name of a game.
+    pub category: Option<String>,
+
+    /// Short keywords describing the stream.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl StreamMetadata {
+    /// Trims all fields, turning empty ones into nothing, and checks their lengths.
+    pub fn validate(self) -> Result<Self, Error> {
+        fn clean(
+            value: Option<String>,
+            field: &'static str,
+            maximum_length: usize,
+        ) -> Result<Option<String>, Error> {
+            let value = value
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty());
+
+            match value {
+                Some(v) if v.chars().count() > maximum_length => Err(Error::TooManyCharacters {
+                    field,
+                    maximum_length: maximum_length as u64,
+                }),
+                value => Ok(value),
+            }
+        }
+
+        let mut tags = Vec::new();
+        for tag in self.tags {
+            // tags are stored separated by commas
+            let tag = tag.replace(',', " ");
+            if let Some(tag) = clean(Some(tag), "tags", MAX_TAG_LENGTH)? {
+                if !tags.iter().any(|t: &String| t.eq_ignore_ascii_case(&tag)) {
+                    tags.push(tag);
+                }
+            }
+        }
+        if tags.len() > MAX_TAGS {
+            return Err(Error::TooManyTags { maximum: MAX_TAGS });
+        }
+
+        Ok(StreamMetadata {
+            title: clean(self.title, "title", MAX_TITLE_LENGTH)?,
+            description: clean(self.description, "description", MAX_DESCRIPTION_LENGTH)?,
+            category: clean(self.category, "category", MAX_CATEGORY_LENGTH)?,
+            tags,
+        })
+    }
+}
+
+fn metadata_from_row(row: &rusqlite::Row) -> rusqlite::Result<StreamMetadata> {
+    let tags: String = row.get(3)?;
+
+    Ok(StreamMetadata {
+        title: row.get(0)?,
+        description: row.get(1)?,
+        category: row.get(2)?,
+        tags: tags
+            .split(',')
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+pub async fn get_metadata(db: &Connection, owner: String) -> anyhow::Result<StreamMetadata> {
+    let metadata = db
+        .call(move |conn| {
+            conn.query_row(
+                "SELECT title, description, category, tags FROM stream_metadata \
+                WHERE username = ?1",
+                params![owner],
+                metadata_from_row,
+            )
+            .optional()
+        })
+        .await
+        .context("Failed to query stream metadata")?;
+
+    Ok(metadata.unwrap_or_default())
+}
+
+/// Gets the metadata of every stream, keyed by the lowercase name of the stream.
+pub async fn get_all_metadata(db: &Connection) -> anyhow::Result<HashMap<String, StreamMetadata>> {
+    db.call(|conn| {
+        let mut stmt = conn
+            .prepare("SELECT title, description, category, tags, username FROM stream_metadata")?;
+
+        let metadata = stmt
+            .query_map(params![], |row| {
+                Ok((
+                    row.get::<_, String>(4)?.to_lowercase(),
+                    metadata_from_row(row)?,
+                ))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        Ok::<_, rusqlite::Error>(metadata)
+    })
+    .await
+    .context("Failed to query stream metadata")
+}
+
+pub async fn set_metadata(
+    db: &Connection,
+    owner: String,
+    metadata: StreamMetadata,
+) -> anyhow::Result<()> {
+    db.call(move |conn| {
+        conn.execute(
+            "INSERT INTO stream_metadata (username, title, description, category, tags) \
+            VALUES (?1, ?2, ?3, ?4, ?5) \
+            ON CONFLICT(username) DO UPDATE SET \
+            title = excluded.title, \
+            description = excluded.description, \
+            category = excluded.category, \
+            tags = excluded.tags",
+            params![
+                owner,
+                metadata.title,
+                metadata.description,
+                metadata.category,
+                metadata.tags.join(",")
+            ],
+        )
+    })
+    .await
+    .context("Failed to update stream metadata")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trims_fields() {
+        let metadata = StreamMetadata {
+            title: Some("  Speedrun  ".into()),
+            description: Some("   ".into()),
+            category: None,
+            tags: vec![" a ".into(), "".into()],
+        }
+        .validate()
+        .unwrap();
+
+        assert_eq!(metadata.title.as_deref(), Some("Speedrun"));
+        assert_eq!(metadata.description, None);
+        assert_eq!(metadata.category, None);
+        assert_eq!(metadata.tags, ["a"]);
+    }
+
+    #[test]
+    fn cleans_tags() {
+        let metadata = StreamMetadata {
+            tags: vec!["Rust".into(), "rust".into(), "a,b".into()],
+            ..Default::default()
+        }
+        .validate()
+        .unwrap();
+
+        assert_eq!(metadata.tags, ["Rust", "a b"]);
+    }
+
+    #[test]
+    fn counts_characters() {
+        let title = "ä".repeat(MAX_TITLE_LENGTH);
+        let metadata = StreamMetadata {
+            title: Some(title.clone()),
+            ..Default::default()
+        };
+        assert_eq!(metadata.validate().unwrap().title, Some(title.clone()));
+
+        let metadata = StreamMetadata {
+            title: Some(title + "ä"),
+            ..Default::default()
+        };
+        assert!(matches!(
+            metadata.validate(),
+            Err(Error::TooManyCharacters { field: "title", .. })
+        ));
+    }
+
+    #[test]
+    fn limits_tags() {
+        let tags = |n| (0..n).map(|i| format!("tag{i}")).collect();
+
+        let metadata = StreamMetadata {
+            tags: tags(MAX_TAGS),
+            ..Default::default()
+        };
+        assert!(metadata.validate().is_ok());
+
+        let metadata = StreamMetadata {
+            tags: tags(MAX_TAGS + 1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            metadata.validate(),
+            Err(Error::TooManyTags { .. })
+        ));
+    }
+}
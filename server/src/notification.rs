@@ -1,6 +1,12 @@
-use std::{env, sync::Arc};
-
-use crate::{Connection, Error};
+use std::{env, sync::Arc, time::Duration};
+
+use crate::{
+    event::{EventSubscriber, StreamEvent},
+    metadata::{self, StreamMetadata},
+    stream::LiveStreamService,
+    token::{ApiAuth, ManageNotifications},
+    Connection, Error,
+};
 
 use anyhow::Context;
 use axum::{
@@ -9,21 +15,264 @@ use axum::{
     Extension, Json, Router,
 };
 use hyper::StatusCode;
-use idlib::{AuthorizeCookie, NoGroups};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rand::rngs::OsRng;
 use rusqlite::{params, OptionalExtension};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
-use tracing::debug;
+use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
 use web_push::{
     ContentEncoding, SubscriptionInfo, SubscriptionKeys, VapidSignatureBuilder, WebPushClient,
     WebPushMessageBuilder,
 };
 
+/// The VAPID `sub` claim used when `WEB_PUSH_SUBJECT` is not set.
+const DEFAULT_VAPID_SUBJECT: &str = "mailto:tmtu+vapid@tmtu.ee";
+
+/// How long before a scheduled stream a reminder is sent, in seconds.
+const REMINDER_LEAD: i64 = 15 * 60;
+
+/// How often scheduled streams are checked for reminders to send.
+const REMINDER_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct StreamNotification {
+enum NotificationKind {
+    StreamStarted,
+    StreamEnded,
+    StreamReminder,
+    Test,
+}
+
+/// The JSON payload of a push notification.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PushNotification {
+    /// What the notification is about.
+    kind: NotificationKind,
+
+    /// The name of the stream the notification is about.
     name: String,
+
+    /// When the stream was started, or is scheduled to start for reminders.
     started: i64,
+
+    /// The title to show in the notification.
+    title: String,
+
+    /// The body text to show in the notification.
+    body: String,
+
+    /// The page to open when the notification is clicked.
+    url: String,
+
+    /// Notifications with the same tag replace each other.
+    tag: String,
+
+    /// What is being streamed, for notifications about live streams.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<PushMetadata>,
+
+    /// The URL of a thumbnail of the stream, for notifications about live streams.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+}
+
+/// The parts of [`StreamMetadata`] included in push notifications.
+///
+/// ### Remarks
+///
+/// Push services limit payloads to around 4KB, which the description and tags could exceed on
+/// their own.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PushMetadata {
+    /// The title of the stream.
+    title: Option<String>,
+
+    /// What kind of content is being streamed.
+    category: Option<String>,
+}
+
+impl From<StreamMetadata> for PushMetadata {
+    fn from(metadata: StreamMetadata) -> Self {
+        PushMetadata {
+            title: metadata.title,
+            category: metadata.category,
+        }
+    }
+}
+
+impl PushNotification {
+    fn stream_started(name: String, metadata: StreamMetadata) -> Self {
+        PushNotification {
+            kind: NotificationKind::StreamStarted,
+            title: format!("{name} is live"),
+            body: metadata
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("{name} started streaming")),
+            url: format!("/{name}"),
+            tag: format!("stream-{name}"),
+            started: OffsetDateTime::now_utc().unix_timestamp(),
+            metadata: Some(metadata.into()),
+            image: Some(format!("/api/stream/{name}/thumbnail.jpg")),
+            name,
+        }
+    }
+
+    fn stream_ended(name: String, metadata: StreamMetadata) -> Self {
+        PushNotification {
+            kind: NotificationKind::StreamEnded,
+            title: format!("{name} stopped streaming"),
+            body: format!("{name}'s stream has ended"),
+            url: format!("/{name}"),
+            tag: format!("stream-{name}"),
+            started: OffsetDateTime::now_utc().unix_timestamp(),
+            metadata: Some(metadata.into()),
+            image: Some(format!("/api/stream/{name}/thumbnail.jpg")),
+            name,
+        }
+    }
+
+    fn stream_reminder(name: String, title: String, starts_at: i64) -> Self {
+        PushNotification {
+            kind: NotificationKind::StreamReminder,
+            title: format!("{name} is streaming soon"),
+            body: title,
+            url: format!("/{name}"),
+            tag: format!("stream-{name}"),
+            started: starts_at,
+            metadata: None,
+            image: None,
+            name,
+        }
+    }
+
+    fn test(name: String) -> Self {
+        PushNotification {
+            kind: NotificationKind::Test,
+            title: "Test notification".into(),
+            body: "Notifications are working".into(),
+            url: "/".into(),
+            tag: "test".into(),
+            started: OffsetDateTime::now_utc().unix_timestamp(),
+            metadata: None,
+            image: None,
+            name,
+        }
+    }
+}
+
+/// Sends push notifications for stream events until the event bus closes.
+pub async fn listen(
+    db: Connection,
+    svc: LiveStreamService,
+    keys: WebPushKeys,
+    mut events: EventSubscriber,
+) {
+    while let Some(event) = events.recv().await {
+        let db = db.clone();
+        let keys = keys.clone();
+
+        match event {
+            // unlisted and private streams are not announced to anyone
+            StreamEvent::Started { stream } | StreamEvent::Stopped { stream }
+                if !svc.is_public(&stream).await =>
+            {
+                debug!("Not sending notifications for non-public stream {stream:?}");
+            }
+            StreamEvent::Started { stream } => {
+                tokio::spawn(async move {
+                    if let Err(e) = on_stream_started(db, keys, stream).await {
+                        error!("Failed to send stream started notifications: {e:?}");
+                    }
+                });
+            }
+            StreamEvent::Stopped { stream } => {
+                tokio::spawn(async move {
+                    if let Err(e) = on_stream_stopped(db, keys, stream).await {
+                        error!("Failed to send stream ended notifications: {e:?}");
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sends reminders for scheduled streams that are about to start, checking periodically.
+pub async fn send_schedule_reminders(db: Connection, keys: WebPushKeys) {
+    let mut interval = tokio::time::interval(REMINDER_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = send_due_reminders(&db, &keys).await {
+            error!("Failed to send scheduled stream reminders: {e:?}");
+        }
+    }
+}
+
+async fn send_due_reminders(db: &Connection, keys: &WebPushKeys) -> anyhow::Result<()> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    let due = db
+        .call(move |conn| {
+            let tx = conn.transaction()?;
+
+            let due = {
+                let mut stmt = tx.prepare(
+                    "SELECT id, username, title, starts_at \
+                    FROM scheduled_streams \
+                    WHERE reminder_sent = 0 AND starts_at <= ?1 AND starts_at > ?2",
+                )?;
+
+                let rows = stmt
+                    .query_map(params![now + REMINDER_LEAD, now], |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            PushNotification::stream_reminder(
+                                row.get(1)?,
+                                row.get(2)?,
+                                row.get(3)?,
+                            ),
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                rows
+            };
+
+            for (id, _) in &due {
+                tx.execute(
+                    "UPDATE scheduled_streams SET reminder_sent = 1 WHERE id = ?1",
+                    params![id],
+                )?;
+            }
+
+            tx.commit()?;
+
+            Ok::<_, rusqlite::Error>(due)
+        })
+        .await
+        .context("Failed to query due scheduled streams")?;
+
+    for (_, notification) in due {
+        debug!(
+            "Sending reminder for scheduled stream of {:?}",
+            notification.name
+        );
+
+        let subscriptions =
+            get_all_notification_subscriptions_for_stream(db.clone(), notification.name.clone())
+                .await;
+
+        send_to_all(keys, subscriptions, &notification).await?;
+    }
+
+    Ok(())
 }
 
 pub async fn on_stream_started(
@@ -33,35 +282,84 @@ pub async fn on_stream_started(
 ) -> anyhow::Result<()> {
     debug!("Sending stream started notification for {name:?}");
 
+    let metadata = metadata::get_metadata(&db, name.clone()).await?;
     let subscriptions = get_all_notification_subscriptions_for_stream(db, name.clone()).await;
 
-    let sig_builder =
-        VapidSignatureBuilder::from_base64_no_sub(&keys.private_key, web_push::STANDARD)?;
-    let client = WebPushClient::new()?;
+    send_to_all(
+        &keys,
+        subscriptions,
+        &PushNotification::stream_started(name, metadata),
+    )
+    .await
+}
 
-    let notification = StreamNotification {
-        name: name.clone(),
-        started: OffsetDateTime::now_utc().unix_timestamp(),
-    };
-    let notification = serde_json::to_string(&notification).unwrap();
+pub async fn on_stream_stopped(
+    db: Connection,
+    keys: WebPushKeys,
+    name: String,
+) -> anyhow::Result<()> {
+    debug!("Sending stream ended notification for {name:?}");
+
+    let metadata = metadata::get_metadata(&db, name.clone()).await?;
+    let subscriptions = get_stream_ended_subscriptions(db).await;
+
+    send_to_all(
+        &keys,
+        subscriptions,
+        &PushNotification::stream_ended(name, metadata),
+    )
+    .await
+}
+
+async fn send_to_all(
+    keys: &WebPushKeys,
+    subscriptions: Vec<AccountSubscriptionInfo>,
+    notification: &PushNotification,
+) -> anyhow::Result<()> {
+    let client = WebPushClient::new()?;
 
     for account_subscription in subscriptions {
         debug!("Sending notification to {}", account_subscription.username);
 
-        let subscription = account_subscription.subscription;
+        if let Err(e) = send_notification(
+            &client,
+            keys,
+            &account_subscription.subscription,
+            notification,
+        )
+        .await
+        {
+            warn!(
+                "Failed to send notification to {}: {e:?}",
+                account_subscription.username
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_notification(
+    client: &WebPushClient,
+    keys: &WebPushKeys,
+    subscription: &SubscriptionInfo,
+    notification: &PushNotification,
+) -> anyhow::Result<()> {
+    let notification = serde_json::to_string(notification)?;
 
-        let mut sig_builder = sig_builder.clone().add_sub_info(&subscription);
-        sig_builder.add_claim("sub", "tmtu+vapid@tmtu.ee");
-        let sig = sig_builder.build()?;
+    let mut sig_builder =
+        VapidSignatureBuilder::from_base64_no_sub(&keys.private_key, web_push::STANDARD)?
+            .add_sub_info(subscription);
+    sig_builder.add_claim("sub", keys.subject.as_str());
+    let sig = sig_builder.build()?;
 
-        let mut builder = WebPushMessageBuilder::new(&subscription)?;
+    let mut builder = WebPushMessageBuilder::new(subscription)?;
 
-        let content = notification.as_bytes();
-        builder.set_payload(ContentEncoding::Aes128Gcm, content);
-        builder.set_vapid_signature(sig);
+    let content = notification.as_bytes();
+    builder.set_payload(ContentEncoding::Aes128Gcm, content);
+    builder.set_vapid_signature(sig);
 
-        client.send(builder.build()?).await?;
-    }
+    client.send(builder.build()?).await?;
 
     Ok(())
 }
@@ -70,17 +368,66 @@ pub async fn on_stream_started(
 pub struct WebPushKeys {
     pub public_key: String,
     pub private_key: String,
+
+    /// The VAPID `sub` claim, a `mailto:` or `https:` contact for the push service.
+    pub subject: String,
 }
 
 impl WebPushKeys {
+    /// Loads the keys from the environment, falling back to keys stored in the database.
+    ///
+    /// ### Remarks
+    ///
+    /// A new keypair is generated and stored if neither has any keys.
+    pub async fn load(db: &Connection) -> anyhow::Result<Self> {
+        if let Some(keys) = WebPushKeys::from_env() {
+            return Ok(keys);
+        }
+
+        db.call(|conn| {
+            let stored = conn
+                .query_row(
+                    "SELECT public_key, private_key FROM web_push_keys",
+                    params![],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .context("Failed to query web push keys")?;
+
+            if let Some((public_key, private_key)) = stored {
+                return Ok(WebPushKeys {
+                    public_key,
+                    private_key,
+                    subject: subject_from_env(),
+                });
+            }
+
+            info!("Generating VAPID keys for web push notifications");
+
+            store_new_keys(conn)
+        })
+        .await
+    }
+
+    /// Replaces the keys stored in the database with a newly generated keypair.
+    ///
+    /// ### Remarks
+    ///
+    /// Subscriptions created with the old keys stop working and have to subscribe again.
+    pub async fn rotate(db: &Connection) -> anyhow::Result<Self> {
+        db.call(|conn| store_new_keys(conn)).await
+    }
+
     pub fn from_env() -> Option<Self> {
         let public_web_push_key = env::var("WEB_PUSH_PUBLIC_KEY_B64");
         let private_web_push_key = env::var("WEB_PUSH_PRIVATE_KEY_B64");
+        let subject = subject_from_env();
 
         if let (Ok(public_key), Ok(private_key)) = (public_web_push_key, private_web_push_key) {
             Some(WebPushKeys {
                 public_key,
                 private_key,
+                subject,
             })
         } else {
             None
@@ -88,12 +435,121 @@ impl WebPushKeys {
     }
 }
 
+fn subject_from_env() -> String {
+    env::var("WEB_PUSH_SUBJECT").unwrap_or_else(|_| DEFAULT_VAPID_SUBJECT.to_string())
+}
+
+fn store_new_keys(conn: &rusqlite::Connection) -> anyhow::Result<WebPushKeys> {
+    let secret = p256::SecretKey::random(&mut OsRng);
+    let public_key = base64::encode(secret.public_key().to_encoded_point(false).as_bytes());
+    let private_key = base64::encode(secret.to_be_bytes());
+
+    conn.execute(
+        "INSERT OR REPLACE INTO web_push_keys \
+        (id, public_key, private_key, created_at) \
+        VALUES (0, ?1, ?2, ?3)",
+        params![
+            &public_key,
+            &private_key,
+            OffsetDateTime::now_utc().unix_timestamp()
+        ],
+    )
+    .context("Failed to store web push keys")?;
+
+    Ok(WebPushKeys {
+        public_key,
+        private_key,
+        subject: subject_from_env(),
+    })
+}
+
 pub fn api_route() -> Router {
     Router::new()
         .route("/key", get(get_public_key))
         .route("/", get(get_notification_settings))
         .route("/", post(post_notification_subscription))
         .route("/", delete(delete_notification_subscription))
+        .route("/test", post(post_test_notification))
+        .route(
+            "/settings",
+            get(get_notification_preferences).put(put_notification_preferences),
+        )
+}
+
+/// Which optional notifications a user receives.
+#[derive(ToSchema, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationPreferences {
+    /// Whether to be notified when a stream ends.
+    stream_ended: bool,
+}
+
+/// Gets which optional notifications the current user receives.
+#[utoipa::path(
+    get,
+    path = "/api/notification/settings",
+    responses(
+        (status = 200, description = "Lists the notification preferences", body = NotificationPreferences),
+    )
+)]
+pub async fn get_notification_preferences(
+    ApiAuth(payload, maybe_token, ..): ApiAuth<ManageNotifications>,
+    Extension(db): Extension<Connection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let name = payload.name;
+            let stream_ended = db
+                .call(move |conn| {
+                    conn.query_row(
+                        "SELECT stream_ended FROM users_notification_settings \
+                        WHERE username = ?1",
+                        params![name],
+                        |row| row.get(0),
+                    )
+                    .optional()
+                })
+                .await
+                .context("Failed to query notification settings")?
+                .unwrap_or(false);
+
+            Ok::<_, Error>(Json(NotificationPreferences { stream_ended }))
+        })
+        .await
+}
+
+/// Changes which optional notifications the current user receives.
+#[utoipa::path(
+    put,
+    path = "/api/notification/settings",
+    request_body = NotificationPreferences,
+    responses(
+        (status = 200, description = "Notification preferences changed successfully"),
+    )
+)]
+pub async fn put_notification_preferences(
+    ApiAuth(payload, maybe_token, ..): ApiAuth<ManageNotifications>,
+    Extension(db): Extension<Connection>,
+    Json(body): Json<NotificationPreferences>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let name = payload.name;
+            db.call(move |conn| {
+                conn.execute(
+                    "INSERT INTO users_notification_settings \
+                    (username, stream_ended) \
+                    VALUES (?1, ?2) \
+                    ON CONFLICT(username) DO UPDATE SET stream_ended = excluded.stream_ended",
+                    params![name, body.stream_ended],
+                )
+            })
+            .await
+            .context("Failed to update notification settings")?;
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
 }
 
 /// Gets the notification settings.
@@ -106,7 +562,7 @@ pub fn api_route() -> Router {
     )
 )]
 pub async fn get_notification_settings(
-    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<NoGroups>,
+    ApiAuth(payload, maybe_token, ..): ApiAuth<ManageNotifications>,
     Extension(db): Extension<Connection>,
 ) -> impl IntoResponse {
     maybe_token
@@ -150,7 +606,7 @@ pub async fn get_public_key(
     )
 )]
 pub async fn post_notification_subscription(
-    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<NoGroups>,
+    ApiAuth(payload, maybe_token, ..): ApiAuth<ManageNotifications>,
     Extension(db): Extension<Connection>,
     Json(body): Json<SubscriptionInfo>,
 ) -> impl IntoResponse {
@@ -159,6 +615,40 @@ pub async fn post_notification_subscription(
         .await
 }
 
+/// Sends a test notification to the current user.
+#[utoipa::path(
+    post,
+    path = "/api/notification/test",
+    responses(
+        (status = 200, description = "Test notification sent successfully"),
+        (status = 404, description = "The user has no notification subscription or web push keys have not been setup"),
+    )
+)]
+pub async fn post_test_notification(
+    ApiAuth(payload, maybe_token, ..): ApiAuth<ManageNotifications>,
+    Extension(db): Extension<Connection>,
+    Extension(keys): Extension<Arc<Option<WebPushKeys>>>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let keys = keys.as_ref().as_ref().ok_or(Error::NotFound)?;
+
+            let name = payload.name;
+            let subscription = {
+                let name = name.clone();
+                db.call(move |conn| get_notification_subscription(conn, &name))
+                    .await
+                    .ok_or(Error::NotFound)?
+            };
+
+            let client = WebPushClient::new().context("Failed to create web push client")?;
+            send_notification(&client, keys, &subscription, &PushNotification::test(name)).await?;
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
 /// Removes a notification subscription.
 #[utoipa::path(
     delete,
@@ -168,7 +658,7 @@ pub async fn post_notification_subscription(
     )
 )]
 pub async fn delete_notification_subscription(
-    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<NoGroups>,
+    ApiAuth(payload, maybe_token, ..): ApiAuth<ManageNotifications>,
     Extension(db): Extension<Connection>,
 ) -> impl IntoResponse {
     maybe_token
@@ -245,11 +735,68 @@ pub fn has_notification_subscription(conn: &rusqlite::Connection, name: &str) ->
     .is_some()
 }
 
+fn get_notification_subscription(
+    conn: &rusqlite::Connection,
+    name: &str,
+) -> Option<SubscriptionInfo> {
+    conn.query_row(
+        "SELECT endpoint, auth, p256dh FROM notification_subscriptions \
+        WHERE username=?1",
+        params![name],
+        |row| {
+            Ok(SubscriptionInfo {
+                endpoint: row.get(0)?,
+                keys: SubscriptionKeys {
+                    auth: row.get(1)?,
+                    p256dh: row.get(2)?,
+                },
+            })
+        },
+    )
+    .optional()
+    .unwrap()
+}
+
 struct AccountSubscriptionInfo {
     username: String,
     subscription: SubscriptionInfo,
 }
 
+async fn get_stream_ended_subscriptions(db: Connection) -> Vec<AccountSubscriptionInfo> {
+    db.call(|conn| {
+        let mut stmt = conn
+            .prepare(
+                "SELECT s.username, s.endpoint, s.auth, s.p256dh \
+                FROM notification_subscriptions s \
+                INNER JOIN users_notification_settings n ON n.username = s.username \
+                WHERE n.stream_ended = 1",
+            )
+            .unwrap();
+
+        let rows = stmt
+            .query_map(params![], subscription_from_row)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        rows
+    })
+    .await
+}
+
+fn subscription_from_row(row: &rusqlite::Row) -> rusqlite::Result<AccountSubscriptionInfo> {
+    Ok(AccountSubscriptionInfo {
+        username: row.get(0)?,
+        subscription: SubscriptionInfo {
+            endpoint: row.get(1)?,
+            keys: SubscriptionKeys {
+                auth: row.get(2)?,
+                p256dh: row.get(3)?,
+            },
+        },
+    })
+}
+
 async fn get_all_notification_subscriptions_for_stream(
     db: Connection,
     name: String,
@@ -260,18 +807,7 @@ async fn get_all_notification_subscriptions_for_stream(
             .unwrap();
 
         let rows = stmt
-            .query_map(params![], |row| {
-                Ok(AccountSubscriptionInfo {
-                    username: row.get(0).unwrap(),
-                    subscription: SubscriptionInfo {
-                        endpoint: row.get(1).unwrap(),
-                        keys: SubscriptionKeys {
-                            auth: row.get(2).unwrap(),
-                            p256dh: row.get(3).unwrap(),
-                        },
-                    },
-                })
-            })
+            .query_map(params![], subscription_from_row)
             .unwrap()
             .collect::<Result<Vec<_>, _>>()
             .unwrap();
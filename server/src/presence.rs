@@ -0,0 +1,199 @@
+use anyhow::Context;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path,
+    },
+    response::Response,
+    Extension,
+};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+use utoipa::ToSchema;
+
+use crate::{
+    access::{self, Viewer},
+    event::{EventSubscriber, StreamEvent},
+    stream::{LiveStreamService, PacketSplitter},
+    Connection, Error,
+};
+
+/// Who is watching a stream.
+#[derive(ToSchema, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewerPresence {
+    /// The names of logged in viewers.
+    names: Vec<String>,
+
+    /// How many viewers are not logged in.
+    anonymous: usize,
+}
+
+impl ViewerPresence {
+    pub async fn of(splitter: &PacketSplitter) -> Self {
+        let mut presence = ViewerPresence::default();
+
+        for session in splitter.viewers().await {
+            match session.name {
+                Some(name) => {
+                    // a viewer can watch from more than one tab
+                    if !presence.names.iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+                        presence.names.push(name);
+                    }
+                }
+                None => presence.anonymous += 1,
+            }
+        }
+
+        presence.names.sort_by_key(|name| name.to_lowercase());
+
+        presence
+    }
+}
+
+/// Gets the streamers that share who is watching their stream.
+pub async fn get_shared_streams(db: &Connection) -> anyhow::Result<Vec<String>> {
+    db.call(|conn| {
+        let mut stmt = conn.prepare("SELECT username FROM users WHERE share_presence = 1")?;
+
+        let rows = stmt
+            .query_map(params![], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok::<_, rusqlite::Error>(rows)
+    })
+    .await
+    .context("Failed to query streams sharing presence")
+}
+
+pub async fn is_shared(db: &Connection, owner: String) -> anyhow::Result<bool> {
+    let shared = db
+        .call(move |conn| {
+            conn.query_row(
+                "SELECT share_presence FROM users WHERE username = ?1",
+                params![owner],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+        .context("Failed to query presence setting")?;
+
+    Ok(shared.unwrap_or(false))
+}
+
+pub async fn set_shared(db: &Connection, owner: String, shared: bool) -> anyhow::Result<()> {
+    db.call(move |conn| {
+        conn.execute(
+            "UPDATE users SET share_presence = ?1 WHERE username = ?2",
+            params![shared, owner],
+        )
+    })
+    .await
+    .context("Failed to update presence setting")?;
+
+    Ok(())
+}
+
+/// Gets who is watching a stream in real time through a websocket connection.
+///
+/// ### Messages
+///
+/// A JSON encoded [`ViewerPresence`] is sent when connecting, and again whenever a viewer joins
+/// or leaves the stream. Messages sent to the WebSocket connection will be ignored.
+///
+/// The connection is closed when the stream stops, or when the streamer stops sharing who is
+/// watching.
+#[utoipa::path(
+    get,
+    path = "/api/stream/{stream}/presence",
+    responses(
+        (status = 101, description = "The stream shares its viewers. Switching to the WebSocket protocol"),
+        (status = 401, description = "The stream is private and the viewer is not logged in"),
+        (status = 403, description = "The viewer is banned, or the stream is private and the viewer is not allowed to watch it"),
+        (status = 404, description = "The stream is not live or does not share its viewers")
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to get the viewers of")
+    )
+)]
+pub async fn get_presence_updates(
+    Path(stream): Path<String>,
+    ws: WebSocketUpgrade,
+    viewer: Viewer,
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+) -> Result<Response, Error> {
+    let live_stream = svc.get_stream(&stream).await.ok_or(Error::NotFound)?;
+    access::authorize_viewer(&db, live_stream.name(), live_stream.visibility(), &viewer).await?;
+
+    if !is_shared(&db, stream.clone()).await? {
+        return Err(Error::NotFound);
+    }
+
+    let splitter = svc
+        .get_splitter_for_stream(&stream)
+        .await
+        .ok_or(Error::NotFound)?;
+    let events = svc.subscribe();
+
+    Ok(ws.on_upgrade(move |socket| {
+        let span = debug_span!("presence", stream = %stream);
+
+        async move {
+            if let Err(e) = websocket_presence(socket, db, stream, splitter, events).await {
+                debug!("Presence connection closed: {e}");
+            }
+        }
+        .instrument(span)
+    }))
+}
+
+async fn websocket_presence(
+    mut socket: WebSocket,
+    db: Connection,
+    stream: String,
+    splitter: PacketSplitter,
+    mut events: EventSubscriber,
+) -> anyhow::Result<()> {
+    let mut presence = ViewerPresence::of(&splitter).await;
+    socket
+        .send(Message::Text(serde_json::to_string(&presence)?))
+        .await?;
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            message = socket.recv() => match message {
+                Some(Ok(_)) => continue,
+                _ => return Ok(()),
+            },
+        };
+
+        match event {
+            Some(StreamEvent::ViewerJoined { stream: s, .. })
+            | Some(StreamEvent::ViewerLeft { stream: s, .. })
+                if s.eq_ignore_ascii_case(&stream) => {}
+            Some(StreamEvent::Stopped { stream: s }) if s.eq_ignore_ascii_case(&stream) => break,
+            Some(_) => continue,
+            None => break,
+        }
+
+        if !is_shared(&db, stream.clone()).await? {
+            break;
+        }
+
+        let new_presence = ViewerPresence::of(&splitter).await;
+        if new_presence != presence {
+            presence = new_presence;
+            socket
+                .send(Message::Text(serde_json::to_string(&presence)?))
+                .await?;
+        }
+    }
+
+    socket.send(Message::Close(None)).await?;
+
+    Ok(())
+}
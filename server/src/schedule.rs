@@ -0,0 +1,198 @@
+use crate::{auth::Authorize, Connection, Error};
+
+use anyhow::Context;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{delete, get},
+    Extension, Json, Router,
+};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::debug;
+use utoipa::ToSchema;
+
+/// How long the title of a scheduled stream can be.
+const MAX_TITLE_LENGTH: usize = 100;
+
+/// How long a scheduled stream stays listed after its start time, in seconds.
+const LISTED_AFTER_START: i64 = 60 * 60;
+
+pub fn api_route() -> Router {
+    Router::new()
+        .route("/", get(get_schedule).post(post_scheduled_stream))
+        .route("/:id", delete(delete_scheduled_stream))
+}
+
+/// A stream that is scheduled to start in the future.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledStreamInfo {
+    /// The ID of the scheduled stream.
+    id: i64,
+
+    /// The name of the stream.
+    name: String,
+
+    /// The title of the scheduled stream.
+    title: String,
+
+    /// When the stream is scheduled to start.
+    starts_at: i64,
+}
+
+/// A request to schedule a stream.
+#[derive(ToSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewScheduledStream {
+    /// The title of the scheduled stream.
+    title: String,
+
+    /// When the stream is scheduled to start, must be in the future.
+    starts_at: i64,
+}
+
+/// Lists upcoming scheduled streams.
+///
+/// ### Remarks
+///
+/// Scheduled streams stay listed for an hour after their start time.
+#[utoipa::path(
+    get,
+    path = "/api/schedule",
+    responses(
+        (status = 200, description = "Listed scheduled streams successfully", body = [ScheduledStreamInfo]),
+    )
+)]
+pub async fn get_schedule(
+    Extension(db): Extension<Connection>,
+) -> Result<Json<Vec<ScheduledStreamInfo>>, Error> {
+    let since = OffsetDateTime::now_utc().unix_timestamp() - LISTED_AFTER_START;
+
+    let schedule = db
+        .call(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, username, title, starts_at \
+                FROM scheduled_streams \
+                WHERE starts_at >= ?1 \
+                ORDER BY starts_at",
+            )?;
+
+            let rows = stmt
+                .query_map(params![since], |row| {
+                    Ok(ScheduledStreamInfo {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                        title: row.get(2)?,
+                        starts_at: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok::<_, rusqlite::Error>(rows)
+        })
+        .await
+        .context("Failed to query scheduled streams")?;
+
+    Ok(Json(schedule))
+}
+
+/// Schedules a stream for the current user.
+///
+/// ### Remarks
+///
+/// Everyone subscribed to notifications gets a reminder shortly before the stream starts.
+#[utoipa::path(
+    post,
+    path = "/api/schedule",
+    request_body = NewScheduledStream,
+    responses(
+        (status = 201, description = "Scheduled stream successfully", body = ScheduledStreamInfo),
+        (status = 400, description = "The title is too long or the start time is in the past"),
+    )
+)]
+pub async fn post_scheduled_stream(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Json(body): Json<NewScheduledStream>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            if body.title.chars().count() > MAX_TITLE_LENGTH {
+                return Err(Error::TooManyCharacters {
+                    field: "title",
+                    maximum_length: MAX_TITLE_LENGTH as u64,
+                });
+            }
+
+            if body.starts_at <= OffsetDateTime::now_utc().unix_timestamp() {
+                return Err(Error::ScheduledInPast);
+            }
+
+            let name = payload.name;
+            let scheduled = db
+                .call(move |conn| {
+                    conn.execute(
+                        "INSERT INTO scheduled_streams \
+                        (username, title, starts_at) \
+                        VALUES (?1, ?2, ?3)",
+                        params![&name, &body.title, body.starts_at],
+                    )
+                    .context("Failed to insert scheduled stream")?;
+
+                    debug!("Scheduled stream for {name:?} at {}", body.starts_at);
+
+                    Ok::<_, anyhow::Error>(ScheduledStreamInfo {
+                        id: conn.last_insert_rowid(),
+                        name,
+                        title: body.title,
+                        starts_at: body.starts_at,
+                    })
+                })
+                .await?;
+
+            Ok((StatusCode::CREATED, Json(scheduled)))
+        })
+        .await
+}
+
+/// Removes a scheduled stream.
+#[utoipa::path(
+    delete,
+    path = "/api/schedule/{id}",
+    responses(
+        (status = 200, description = "Removed scheduled stream successfully"),
+        (status = 404, description = "The user has no scheduled stream with the given ID"),
+    ),
+    params(
+        ("id" = i64, Path, description = "The ID of the scheduled stream")
+    )
+)]
+pub async fn delete_scheduled_stream(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let name = payload.name;
+            let removed = db
+                .call(move |conn| {
+                    conn.execute(
+                        "DELETE FROM scheduled_streams WHERE id = ?1 AND username = ?2",
+                        params![id, name],
+                    )
+                })
+                .await
+                .context("Failed to delete scheduled stream")?;
+
+            if removed == 0 {
+                return Err(Error::NotFound);
+            }
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
@@ -1,10 +1,15 @@
 use anyhow::Context;
 use axum::{
-    body::StreamBody, extract::Path, http::HeaderValue, response::Response, routing::get,
+    body::StreamBody,
+    extract::{Path, Query},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
     Extension, Json, Router,
 };
 use bytes::Bytes;
 use futures::stream::{self, Stream};
+use idlib::SecretKey;
 use tracing::*;
 use mediabox::{
     format::{
@@ -12,39 +17,74 @@ use mediabox::{
         rtmp::{RtmpListener, RtmpRequest},
         Movie,
     },
-    Packet, Span,
+    MediaKind, Packet, Span,
 };
+use rand::{rngs::StdRng, RngCore, SeedableRng};
 use rusqlite::params;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use tokio::sync::{
     mpsc::{self, Receiver, Sender},
-    RwLock,
+    Notify, RwLock,
 };
 use tokio_rusqlite::Connection;
 use tracing::{debug_span, Instrument};
 use utoipa::ToSchema;
 
-use std::{collections::HashMap, io, sync::Arc, net::SocketAddr};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    notification::{self, WebPushKeys},
-    Error,
+    access::{self, UnlockLimiter, Viewer},
+    auth::Authorize,
+    event::{EventBus, EventSubscriber, IngestStats, StreamEvent},
+    live::TrackKind,
+    metadata::{self, StreamMetadata},
+    presence::{self, ViewerPresence},
+    thumbnail::{self, Thumbnail},
+    token::{ApiAuth, ReadStreams},
+    transcode, Error,
 };
 
 pub fn api_route() -> Router {
     Router::new()
         .route("/", get(get_streams))
+        .route("/:stream", get(get_stream))
         .route("/:stream/preview", get(get_preview))
+        .route("/:stream/thumbnail.jpg", get(thumbnail::get_thumbnail))
+        .route("/:stream/stats", get(get_stats))
+        .route("/:stream/unlock", post(post_unlock_stream))
+        .route("/:stream/presence", get(presence::get_presence_updates))
+        .route("/:stream/viewers", get(get_viewers))
+        .route("/:stream/viewers/:id/kick", post(post_kick_viewer))
+        .route("/:stream/viewers/:id/ban", post(post_ban_viewer))
+        .route("/:stream/bans", get(get_bans))
+        .route("/:stream/bans/:id", delete(delete_ban))
 }
 
+/// How often ingest statistics are published.
+const INGEST_STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How much audio is cached for the preview of streams without video.
+const AUDIO_PREVIEW_LENGTH: Duration = Duration::from_secs(2);
+
 async fn handle_rtmp_request(
     db: Connection,
     svc: LiveStreamService,
-    keys: Option<WebPushKeys>,
+    bind_addr: SocketAddr,
     request: RtmpRequest,
 ) -> anyhow::Result<()> {
+    if request.app() == transcode::RENDITION_APP {
+        return transcode::handle_rendition(svc, request).await;
+    }
+
     let key = request.key().to_string();
+    let visibility = Visibility::from_app(request.app());
 
     let account = get_account_by_stream_key(&db, key).await?;
 
@@ -60,33 +100,64 @@ async fn handle_rtmp_request(
             tracks,
             attachments: Vec::new(),
         };
-
-        let (mut splitter, gop) = svc.new_stream(account.username.clone(), movie).await?;
-
-        if let Some(keys) = keys {
-            let db = db.clone();
-            let keys = keys.clone();
-            let username = account.username.clone();
-            tokio::spawn(async move {
-                if let Err(e) = notification::on_stream_started(
-                    db,
-                    keys,
-                    username,
-                ).await {
-                    error!("Failed to send stream started notifications: {e:?}");
-                }
-            });
+        let has_video = movie.tracks.iter().any(|track| track.is_video());
+
+        let (mut splitter, gop, stop) = svc
+            .new_stream(account.username.clone(), movie.clone(), visibility)
+            .await?;
+
+        if has_video {
+            transcode::start_transcoders(&svc, bind_addr, &account.username, &movie).await;
+
+            if let Some(stream) = svc.get_stream(&account.username).await {
+                tokio::spawn(
+                    thumbnail::generate_thumbnails(
+                        svc.clone(),
+                        account.username.clone(),
+                        stream.started(),
+                    )
+                    .in_current_span(),
+                );
+            }
         }
 
         let mut new_gop = Vec::new();
+        let mut new_gop_started = Instant::now();
+        let mut stats = IngestStatsCollector::new();
         loop {
-            match session.read_frame().await {
+            let frame = tokio::select! {
+                frame = session.read_frame() => frame,
+                _ = stop.notified() => {
+                    info!("Stream was forcefully stopped");
+                    svc.stop_stream(account.username).await;
+
+                    return Ok(());
+                }
+            };
+
+            match frame {
                 Ok(pkt) => {
-                    if pkt.track.is_video() {
-                        if pkt.key {
+                    if let Some(stats) = stats.record(&pkt) {
+                        svc.record_ingest_stats(&account.username, stats).await;
+                    }
+
+                    // streams without video get a preview of the latest few seconds of audio
+                    let (is_cached, starts_gop) = if has_video {
+                        // audio is cached along with the video once a keyframe has been received
+                        (
+                            pkt.track.is_video() || !new_gop.is_empty(),
+                            pkt.track.is_video() && pkt.key,
+                        )
+                    } else {
+                        (true, new_gop_started.elapsed() >= AUDIO_PREVIEW_LENGTH)
+                    };
+
+                    if is_cached {
+                        if starts_gop {
                             let mut gop = gop.write().await;
                             *gop = new_gop.clone();
                             new_gop.clear();
+                            new_gop_started = Instant::now();
                         }
                         new_gop.push(pkt.clone());
                     }
@@ -106,31 +177,85 @@ async fn handle_rtmp_request(
     fut.instrument(span).await
 }
 
+struct IngestStatsCollector {
+    since: Instant,
+    bytes: u64,
+    video_frames: u64,
+}
+
+impl IngestStatsCollector {
+    fn new() -> Self {
+        IngestStatsCollector {
+            since: Instant::now(),
+            bytes: 0,
+            video_frames: 0,
+        }
+    }
+
+    /// Records a packet, returning the stats once a full interval has passed.
+    fn record(&mut self, pkt: &Packet) -> Option<IngestStats> {
+        self.bytes += pkt.buffer.len() as u64;
+        if pkt.track.is_video() {
+            self.video_frames += 1;
+        }
+
+        let elapsed = self.since.elapsed();
+        if elapsed < INGEST_STATS_INTERVAL {
+            return None;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        let stats = IngestStats {
+            bitrate: (self.bytes as f64 * 8.0 / seconds) as u64,
+            framerate: (self.video_frames as f64 / seconds) as f32,
+        };
+
+        *self = IngestStatsCollector::new();
+
+        Some(stats)
+    }
+}
+
 struct Account {
     username: String,
     stream_key: String,
 }
 
 async fn get_account_by_stream_key(db: &Connection, key: String) -> anyhow::Result<Account> {
-    db.call(move |conn| {
-        conn.query_row(
-            "SELECT username, stream_key FROM users WHERE stream_key = ?1",
-            params![key],
-            |r| {
-                Ok(Account {
-                    username: r.get(0).unwrap(),
-                    stream_key: r.get(1).unwrap(),
-                })
-            },
-        )
-        .context("Failed to find account by stream key")
-    })
-    .await
+    let (account, streaming_disabled, can_stream) = db
+        .call(move |conn| {
+            conn.query_row(
+                "SELECT username, stream_key, streaming_disabled, can_stream \
+                FROM users WHERE stream_key = ?1",
+                params![key],
+                |r| {
+                    Ok((
+                        Account {
+                            username: r.get(0).unwrap(),
+                            stream_key: r.get(1).unwrap(),
+                        },
+                        r.get::<_, bool>(2).unwrap(),
+                        r.get::<_, bool>(3).unwrap(),
+                    ))
+                },
+            )
+            .context("Failed to find account by stream key")
+        })
+        .await?;
+
+    if streaming_disabled {
+        anyhow::bail!("Streaming is disabled for {:?}", account.username);
+    }
+
+    if !can_stream {
+        anyhow::bail!("{:?} has not redeemed an invite", account.username);
+    }
+
+    Ok(account)
 }
 
 pub async fn listen(
     db: Connection,
-    keys: Option<WebPushKeys>,
     svc: LiveStreamService,
     bind_addr: SocketAddr,
 ) -> anyhow::Result<()> {
@@ -141,10 +266,9 @@ pub async fn listen(
 
         let db = db.clone();
         let svc = svc.clone();
-        let keys = keys.clone();
 
         let future = async move {
-            if let Err(e) = handle_rtmp_request(db, svc, keys, request).await {
+            if let Err(e) = handle_rtmp_request(db, svc, bind_addr, request).await {
                 error!("{}", e);
             }
         };
@@ -159,15 +283,49 @@ pub struct LiveStreams(pub Arc<RwLock<HashMap<String, LiveStream>>>);
 #[derive(Clone)]
 pub struct LiveStreamService {
     streams: Arc<RwLock<HashMap<String, LiveStream>>>,
+    events: EventBus,
+
+    /// The stream and rendition of each key a transcoder can send a rendition with.
+    rendition_keys: Arc<RwLock<HashMap<String, (String, String)>>>,
 }
 
 impl LiveStreamService {
     pub fn new() -> Self {
         LiveStreamService {
             streams: Arc::new(RwLock::new(HashMap::new())),
+            events: EventBus::new(),
+            rendition_keys: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
+    /// Creates a single-use key for sending a rendition of a stream over RTMP.
+    pub async fn create_rendition_key(&self, stream: String, rendition: String) -> String {
+        let mut key_bytes = [0u8; 16];
+        StdRng::from_entropy().fill_bytes(&mut key_bytes[..]);
+        let key = hex::encode(key_bytes);
+
+        self.rendition_keys
+            .write()
+            .await
+            .insert(key.clone(), (stream, rendition));
+
+        key
+    }
+
+    /// Gets the stream and rendition a key was created for, and invalidates it.
+    pub async fn take_rendition_key(&self, key: &str) -> Option<(String, String)> {
+        self.rendition_keys.write().await.remove(key)
+    }
+
+    /// Subscribes to lifecycle events of all streams.
+    pub fn subscribe(&self) -> EventSubscriber {
+        self.events.subscribe()
+    }
+
+    pub fn publish(&self, event: StreamEvent) {
+        self.events.publish(event);
+    }
+
     pub async fn get_splitter_for_stream(&self, stream: &str) -> Option<PacketSplitter> {
         if let Some(stream) = self.streams.read().await.get(stream) {
             stream.splitter.read().await.clone()
@@ -180,7 +338,12 @@ impl LiveStreamService {
         &self,
         username: String,
         movie: Movie,
-    ) -> anyhow::Result<(PacketSplitter, Arc<RwLock<Vec<mediabox::Packet>>>)> {
+        visibility: Visibility,
+    ) -> anyhow::Result<(
+        PacketSplitter,
+        Arc<RwLock<Vec<mediabox::Packet>>>,
+        Arc<Notify>,
+    )> {
         let mut streams = self.streams.write().await;
 
         let stream = streams
@@ -190,10 +353,41 @@ impl LiveStreamService {
             anyhow::bail!("Stream for {username:?} is already live");
         }
 
-        let splitter = stream.start_stream(movie).await;
+        let splitter = stream.start_stream(movie, visibility).await;
         let gop = stream.gop.clone();
+        let stop = stream.stop.clone();
 
-        Ok((splitter, gop))
+        self.events.publish(StreamEvent::Started { stream: username });
+
+        Ok((splitter, gop, stop))
+    }
+
+    /// Terminates the RTMP session of a live stream.
+    ///
+    /// Returns `false` if the stream is not live.
+    pub async fn force_stop_stream(&self, username: &str) -> bool {
+        let streams = self.streams.read().await;
+
+        match streams.get(username) {
+            Some(stream) if stream.is_live => {
+                stream.stop.notify_one();
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stores the latest ingest statistics of a stream and publishes them.
+    pub async fn record_ingest_stats(&self, username: &str, stats: IngestStats) {
+        if let Some(stream) = self.streams.write().await.get_mut(username) {
+            stream.ingest_stats = Some(stats);
+        }
+
+        self.events.publish(StreamEvent::IngestStats {
+            stream: username.to_string(),
+            stats,
+        });
     }
 
     pub async fn stop_stream(&self, username: String) {
@@ -201,11 +395,20 @@ impl LiveStreamService {
 
         if let Some(stream) = streams.get_mut(&username) {
             stream.stop_stream().await;
+
+            self.events.publish(StreamEvent::Stopped { stream: username });
         } else {
             warn!("Did not find stream for {username:?} when stopping");
         }
     }
 
+    /// Whether a stream is listed for everyone, as opposed to being unlisted or private.
+    pub async fn is_public(&self, username: &str) -> bool {
+        self.get_stream(username)
+            .await
+            .is_some_and(|stream| stream.visibility() == Visibility::Public)
+    }
+
     pub async fn get_stream(&self, username: &str) -> Option<LiveStream> {
         self.streams.read().await.get(username).cloned()
     }
@@ -215,14 +418,86 @@ impl LiveStreamService {
     }
 }
 
+/// Who can find and watch a stream.
+///
+/// ### Remarks
+///
+/// The visibility is chosen by the RTMP app the stream is sent to, e.g.
+/// `rtmp://<host>/public`.
+#[derive(ToSchema, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Visibility {
+    /// Listed on the stream list and watchable by anyone.
+    Public,
+
+    /// Hidden from the stream list but watchable by anyone with a link.
+    Unlisted,
+
+    /// Hidden from the stream list and only watchable by the streamer's allowlist.
+    Private,
+}
+
+impl Visibility {
+    fn from_app(app: &str) -> Self {
+        match app {
+            "public" => Visibility::Public,
+            "private" => Visibility::Private,
+            _ => Visibility::Unlisted,
+        }
+    }
+}
+
+/// What kind of media a stream has.
+#[derive(ToSchema, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MediaType {
+    /// The stream has both video and audio.
+    AudioVideo,
+
+    /// The stream has video but no audio.
+    Video,
+
+    /// The stream has audio but no video, e.g. music or a podcast.
+    Audio,
+}
+
+impl MediaType {
+    pub fn of(movie: &Movie) -> Self {
+        let has_video = movie.tracks.iter().any(|track| track.is_video());
+        let has_audio = movie.tracks.iter().any(|track| track.is_audio());
+
+        Self::new(has_video, has_audio)
+    }
+
+    fn new(has_video: bool, has_audio: bool) -> Self {
+        match (has_video, has_audio) {
+            (true, true) => MediaType::AudioVideo,
+            (true, false) => MediaType::Video,
+            _ => MediaType::Audio,
+        }
+    }
+
+    /// The MIME type of MP4 files with this kind of media.
+    pub fn mp4_mime_type(self) -> &'static str {
+        match self {
+            MediaType::Audio => "audio/mp4",
+            _ => "video/mp4",
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct LiveStream {
     name: String,
     started: OffsetDateTime,
     stopped_streaming: Option<OffsetDateTime>,
     is_live: bool,
+    visibility: Visibility,
     splitter: Arc<RwLock<Option<PacketSplitter>>>,
     gop: Arc<RwLock<Vec<mediabox::Packet>>>,
+    stop: Arc<Notify>,
+    ingest_stats: Option<IngestStats>,
+    thumbnail: Arc<RwLock<Option<Thumbnail>>>,
 }
 
 impl LiveStream {
@@ -232,16 +507,24 @@ impl LiveStream {
             started: OffsetDateTime::now_utc(),
             stopped_streaming: None,
             is_live: false,
+            visibility: Visibility::Unlisted,
             splitter: Arc::new(RwLock::new(None)),
             gop: Arc::new(RwLock::new(Vec::new())),
+            stop: Arc::new(Notify::new()),
+            ingest_stats: None,
+            thumbnail: Arc::new(RwLock::new(None)),
         }
     }
 
-    pub async fn start_stream(&mut self, movie: Movie) -> PacketSplitter {
-        info!("Starting stream for {:?}", self.name);
+    pub async fn start_stream(&mut self, movie: Movie, visibility: Visibility) -> PacketSplitter {
+        info!("Starting {visibility:?} stream for {:?}", self.name);
 
         self.is_live = true;
+        self.visibility = visibility;
         self.started = OffsetDateTime::now_utc();
+        // don't let a stop request for an earlier session end this one
+        self.stop = Arc::new(Notify::new());
+        self.ingest_stats = None;
 
         let splitter = PacketSplitter::new(movie);
         *self.splitter.write().await = Some(splitter.clone());
@@ -255,12 +538,190 @@ impl LiveStream {
         self.is_live = false;
         self.stopped_streaming = Some(OffsetDateTime::now_utc());
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.is_live
+    }
+
+    pub fn started(&self) -> OffsetDateTime {
+        self.started
+    }
+
+    pub async fn viewer_count(&self) -> usize {
+        match &*self.splitter.read().await {
+            Some(splitter) => splitter.viewer_count().await,
+            None => 0,
+        }
+    }
+
+    /// Muxes the most recently cached GOP into a short MP4, or returns `None` if nothing has been
+    /// cached yet or the stream has no tracks of the given `kind`.
+    pub async fn preview(
+        &self,
+        kind: Option<TrackKind>,
+    ) -> anyhow::Result<Option<(MediaType, Span)>> {
+        let gop = self.gop.read().await;
+        if gop.is_empty() {
+            return Ok(None);
+        }
+
+        let mut movie = match &*self.splitter.read().await {
+            Some(splitter) => splitter.movie.clone(),
+            None => return Ok(None),
+        };
+
+        movie
+            .tracks
+            .retain(|track| kind.is_none_or(|kind| kind.matches(track)));
+        if movie.tracks.is_empty() {
+            return Ok(None);
+        }
+
+        let packets = gop
+            .iter()
+            .filter(|pkt| movie.tracks.iter().any(|track| track.id == pkt.track.id))
+            .cloned()
+            .collect();
+        let mp4 = snapshot_mp4(&movie, packets)?;
+
+        Ok(Some((MediaType::of(&movie), mp4)))
+    }
+
+    pub async fn thumbnail(&self) -> Option<Thumbnail> {
+        self.thumbnail.read().await.clone()
+    }
+
+    pub async fn set_thumbnail(&self, thumbnail: Thumbnail) {
+        *self.thumbnail.write().await = Some(thumbnail);
+    }
+
+    /// Describes the stream to viewers, looking up its settings.
+    pub async fn describe(&self, db: &Connection) -> Result<LiveStreamInfo, Error> {
+        let locked = access::get_locked_streams(db).await?;
+        let shared = presence::is_shared(db, self.name.clone()).await?;
+        let metadata = metadata::get_metadata(db, self.name.clone()).await?;
+
+        Ok(self
+            .info(
+                locked.iter().any(|l| l.eq_ignore_ascii_case(&self.name)),
+                shared,
+                metadata,
+            )
+            .await)
+    }
+
+    /// Describes the stream to viewers.
+    async fn info(&self, locked: bool, shared: bool, metadata: StreamMetadata) -> LiveStreamInfo {
+        let splitter = self.splitter.read().await;
+        let viewers = if let Some(splitter) = &*splitter {
+            splitter.viewer_count().await
+        } else {
+            0
+        };
+
+        let watching = match &*splitter {
+            Some(splitter) if shared => Some(ViewerPresence::of(splitter).await),
+            _ => None,
+        };
+
+        LiveStreamInfo {
+            name: self.name.clone(),
+            viewers,
+            media_type: splitter.as_ref().map(|s| MediaType::of(&s.movie)),
+            is_live: splitter.is_some(),
+            started: self.started.unix_timestamp(),
+            stopped: self.stopped_streaming.map(|t| t.unix_timestamp()),
+            locked,
+            watching,
+            metadata,
+        }
+    }
+}
+
+/// A single connection watching a stream.
+#[derive(Clone, Debug)]
+pub struct ViewerSession {
+    /// A random ID identifying the connection.
+    pub id: String,
+
+    /// The name of the viewer, if they are logged in.
+    pub name: Option<String>,
+
+    /// The IP address the viewer is connecting from.
+    pub addr: Option<IpAddr>,
+
+    /// When the viewer started watching.
+    pub joined: OffsetDateTime,
+}
+
+impl ViewerSession {
+    pub fn new(viewer: &Viewer) -> Self {
+        let mut id_bytes = [0u8; 8];
+        StdRng::from_entropy().fill_bytes(&mut id_bytes[..]);
+
+        ViewerSession {
+            id: hex::encode(id_bytes),
+            name: viewer.name.clone(),
+            addr: viewer.addr,
+            joined: OffsetDateTime::now_utc(),
+        }
+    }
+
+    /// Whether this session belongs to the same viewer as `other`.
+    ///
+    /// Logged in viewers are matched by name and anonymous viewers by IP address.
+    fn same_viewer(&self, other: &ViewerSession) -> bool {
+        match (&self.name, &other.name) {
+            (Some(name), Some(other_name)) => name.eq_ignore_ascii_case(other_name),
+            (None, None) => self.addr.is_some() && self.addr == other.addr,
+            _ => false,
+        }
+    }
+}
+
+/// A packet along with when it was received from the streamer.
+#[derive(Clone)]
+pub struct IngestedPacket {
+    pub packet: Packet,
+    pub ingested_at: OffsetDateTime,
+
+    /// The transcoded rendition the packet belongs to, or `None` for the original stream.
+    pub rendition: Option<Arc<str>>,
+}
+
+struct SplitterTarget {
+    session: ViewerSession,
+    sender: Sender<IngestedPacket>,
+
+    /// The transcoded rendition the viewer is watching, or `None` for the original stream.
+    rendition: Option<String>,
+
+    /// The rendition the viewer is switching to. Its packets are sent along with those of
+    /// `rendition` until the viewer reaches a keyframe to switch at.
+    switching_to: Option<Option<String>>,
+
+    /// The latest glass-to-glass latency reported by the viewer, in milliseconds.
+    latency: Option<f64>,
 }
 
 #[derive(Clone)]
 pub struct PacketSplitter {
-    targets: Arc<RwLock<Vec<Sender<mediabox::Packet>>>>,
+    targets: Arc<RwLock<Vec<SplitterTarget>>>,
     movie: Movie,
+
+    /// The tracks of each transcoded rendition, by name.
+    renditions: Arc<RwLock<HashMap<String, Movie>>>,
+
+    /// Outputs of the original stream that are not viewers, such as transcoders.
+    taps: Arc<RwLock<Vec<Sender<IngestedPacket>>>>,
 }
 
 impl PacketSplitter {
@@ -268,26 +729,163 @@ impl PacketSplitter {
         PacketSplitter {
             targets: Arc::new(RwLock::new(Vec::new())),
             movie,
+            renditions: Arc::new(RwLock::new(HashMap::new())),
+            taps: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    /// Adds a viewer of the original stream, or of a transcoded rendition.
+    ///
+    /// Returns `None` if there is no rendition with the given name.
+    pub async fn attach(
+        &mut self,
+        session: ViewerSession,
+        rendition: Option<&str>,
+    ) -> Option<(Movie, Receiver<IngestedPacket>)> {
+        let movie = self.rendition_movie(rendition).await?;
+
+        let (sender, recv) = mpsc::channel(512);
+
+        self.targets.write().await.push(SplitterTarget {
+            session,
+            sender,
+            rendition: rendition.map(str::to_string),
+            switching_to: None,
+            latency: None,
+        });
+
+        Some((movie, recv))
+    }
+
+    /// Gets the tracks of a transcoded rendition, or of the original stream for `None`.
+    pub async fn rendition_movie(&self, rendition: Option<&str>) -> Option<Movie> {
+        match rendition {
+            Some(rendition) => self.renditions.read().await.get(rendition).cloned(),
+            None => Some(self.movie.clone()),
+        }
+    }
+
+    /// Starts sending packets of another rendition to a viewer along with the current one,
+    /// until `finish_switch` is called.
+    pub async fn start_switch(&self, session_id: &str, rendition: Option<&str>) {
+        let mut targets = self.targets.write().await;
+
+        if let Some(target) = targets.iter_mut().find(|t| t.session.id == session_id) {
+            target.switching_to = Some(rendition.map(str::to_string));
+        }
+    }
+
+    /// Stops sending packets of the rendition a viewer switched away from.
+    pub async fn finish_switch(&self, session_id: &str) {
+        let mut targets = self.targets.write().await;
+
+        if let Some(target) = targets.iter_mut().find(|t| t.session.id == session_id) {
+            if let Some(rendition) = target.switching_to.take() {
+                target.rendition = rendition;
+            }
         }
     }
 
-    pub async fn attach(&mut self) -> (Movie, Receiver<Packet>) {
-        let (send, recv) = mpsc::channel(512);
+    /// Gets the packets of the original stream without counting as a viewer.
+    pub async fn tap(&self) -> (Movie, Receiver<IngestedPacket>) {
+        let (sender, recv) = mpsc::channel(512);
 
-        self.targets.write().await.push(send);
+        self.taps.write().await.push(sender);
 
         (self.movie.clone(), recv)
     }
 
+    pub async fn add_rendition(&self, name: String, movie: Movie) {
+        self.renditions.write().await.insert(name, movie);
+    }
+
+    /// Removes a rendition so that no new viewers can select it.
+    pub async fn remove_rendition(&self, name: &str) {
+        self.renditions.write().await.remove(name);
+    }
+
+    /// Disconnects every viewer of a rendition, and cancels switching to it.
+    pub async fn disconnect_rendition(&self, name: &str) {
+        let mut targets = self.targets.write().await;
+
+        targets.retain(|target| target.rendition.as_deref() != Some(name));
+        for target in targets.iter_mut() {
+            if target
+                .switching_to
+                .as_ref()
+                .is_some_and(|r| r.as_deref() == Some(name))
+            {
+                target.switching_to = None;
+            }
+        }
+    }
+
+    /// Gets the names of the available renditions.
+    pub async fn renditions(&self) -> Vec<String> {
+        let mut renditions = self
+            .renditions
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        renditions.sort();
+
+        renditions
+    }
+
     pub async fn write_packet(&mut self, packet: mediabox::Packet) {
+        let packet = IngestedPacket {
+            packet,
+            ingested_at: OffsetDateTime::now_utc(),
+            rendition: None,
+        };
+
+        self.taps
+            .write()
+            .await
+            .retain(|tap| match tap.try_send(packet.clone()) {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!("Closing splitter tap: {e}");
+                    false
+                }
+            });
+
+        self.send_to_targets(None, packet).await;
+    }
+
+    pub async fn write_rendition_packet(&mut self, rendition: &str, packet: mediabox::Packet) {
+        let packet = IngestedPacket {
+            packet,
+            ingested_at: OffsetDateTime::now_utc(),
+            rendition: Some(Arc::from(rendition)),
+        };
+
+        self.send_to_targets(Some(rendition), packet).await;
+    }
+
+    async fn send_to_targets(&self, rendition: Option<&str>, packet: IngestedPacket) {
         let mut targets = self.targets.write().await;
 
         #[allow(clippy::needless_collect)]
         let targets_to_remove = targets
             .iter()
-            .map(|send| send.try_send(packet.clone()))
             .enumerate()
-            .filter_map(|(idx, res)| res.err().map(|e| (idx, e)))
+            .filter(|(_, target)| {
+                target.rendition.as_deref() == rendition
+                    || target
+                        .switching_to
+                        .as_ref()
+                        .is_some_and(|r| r.as_deref() == rendition)
+            })
+            .filter_map(|(idx, target)| {
+                target
+                    .sender
+                    .try_send(packet.clone())
+                    .err()
+                    .map(|e| (idx, e))
+            })
             .collect::<Vec<_>>();
 
         for (idx, result) in targets_to_remove.into_iter().rev() {
@@ -306,8 +904,67 @@ impl PacketSplitter {
         }
     }
 
-    async fn viewer_count(&self) -> usize {
-        self.targets.read().await.len()
+    pub async fn viewer_count(&self) -> usize {
+        self.targets
+            .read()
+            .await
+            .iter()
+            .filter(|target| !target.sender.is_closed())
+            .count()
+    }
+
+    pub async fn viewers(&self) -> Vec<ViewerSession> {
+        self.targets
+            .read()
+            .await
+            .iter()
+            .filter(|target| !target.sender.is_closed())
+            .map(|target| target.session.clone())
+            .collect()
+    }
+
+    /// Stores the latency reported by the viewer of a session.
+    pub async fn report_latency(&self, session_id: &str, latency: f64) {
+        let mut targets = self.targets.write().await;
+
+        if let Some(target) = targets.iter_mut().find(|t| t.session.id == session_id) {
+            target.latency = Some(latency);
+        }
+    }
+
+    /// Gets the latest latencies reported by current viewers, in milliseconds.
+    pub async fn latencies(&self) -> Vec<f64> {
+        self.targets
+            .read()
+            .await
+            .iter()
+            .filter(|target| !target.sender.is_closed())
+            .filter_map(|target| target.latency)
+            .collect()
+    }
+
+    /// Disconnects every session matching `predicate`, returning the removed sessions.
+    ///
+    /// ### Remarks
+    ///
+    /// Dropping the packet sender ends the viewer's WebSocket connection.
+    pub async fn remove_viewers(
+        &self,
+        predicate: impl Fn(&ViewerSession) -> bool,
+    ) -> Vec<ViewerSession> {
+        let mut targets = self.targets.write().await;
+
+        let mut removed = Vec::new();
+        targets.retain(|target| {
+            if predicate(&target.session) {
+                removed.push(target.session.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        removed
     }
 }
 
@@ -325,6 +982,9 @@ pub struct LiveStreamInfo {
     /// How many viewers the stream has.
     viewers: usize,
 
+    /// What kind of media the stream has, if it has been live since the server started.
+    media_type: Option<MediaType>,
+
     /// Whether the stream is currently live.
     is_live: bool,
 
@@ -333,6 +993,15 @@ pub struct LiveStreamInfo {
 
     /// When the stream was stopped, if it's not live.
     stopped: Option<i64>,
+
+    /// Whether the stream needs a password to watch.
+    locked: bool,
+
+    /// Who is watching the stream, if the streamer shares it.
+    watching: Option<ViewerPresence>,
+
+    /// What is being streamed.
+    metadata: StreamMetadata,
 }
 
 /// Gets a list of all public livestreams.
@@ -344,69 +1013,258 @@ pub struct LiveStreamInfo {
     )
 )]
 pub async fn get_streams(
+    Extension(db): Extension<Connection>,
     Extension(svc): Extension<LiveStreamService>,
 ) -> Result<Json<Vec<LiveStreamInfo>>, Error> {
+    Ok(Json(get_public_streams(&db, &svc).await?))
+}
+
+/// Describes all public livestreams.
+pub async fn get_public_streams(
+    db: &Connection,
+    svc: &LiveStreamService,
+) -> Result<Vec<LiveStreamInfo>, Error> {
     let streams = svc.get_all_streams().await;
+    let locked = access::get_locked_streams(db).await?;
+    let shared = presence::get_shared_streams(db).await?;
+    let mut all_metadata = metadata::get_all_metadata(db).await?;
 
     let mut all_streams = Vec::new();
     for stream in streams.iter() {
-        let splitter = stream.splitter.read().await;
-        let viewers = if let Some(splitter) = &*splitter {
-            splitter.viewer_count().await
+        if stream.visibility != Visibility::Public {
+            continue;
+        }
+
+        let info = stream
+            .info(
+                locked.iter().any(|l| l.eq_ignore_ascii_case(&stream.name)),
+                shared.iter().any(|s| s.eq_ignore_ascii_case(&stream.name)),
+                all_metadata
+                    .remove(&stream.name.to_lowercase())
+                    .unwrap_or_default(),
+            )
+            .await;
+
+        all_streams.push(info);
+    }
+
+    Ok(all_streams)
+}
+
+/// Detailed information about a single livestream.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveStreamDetails {
+    #[serde(flatten)]
+    info: LiveStreamInfo,
+
+    /// The codecs of the stream, as used in a MIME type, if it's live.
+    codecs: Option<String>,
+
+    /// The width of the video, if it's live.
+    width: Option<u32>,
+
+    /// The height of the video, if it's live.
+    height: Option<u32>,
+
+    /// How many video frames are received per second, once measured.
+    framerate: Option<f32>,
+
+    /// The bitrate of all tracks in bits per second, once measured.
+    bitrate: Option<u64>,
+
+    /// The names of the transcoded renditions that can be watched instead of the original.
+    renditions: Vec<String>,
+}
+
+/// Gets information about a single livestream.
+///
+/// ### Remarks
+///
+/// Unlike [`get_streams`] this also returns unlisted and private streams, as long as the viewer
+/// is allowed to watch them. Password protected streams are returned without being unlocked.
+#[utoipa::path(
+    get,
+    path = "/api/stream/{stream}",
+    responses(
+        (status = 200, description = "Returned the stream", body = LiveStreamDetails),
+        (status = 401, description = "The stream is private and the viewer is not logged in"),
+        (status = 403, description = "The viewer is banned, or the stream is private and the viewer is not allowed to watch it"),
+        (status = 404, description = "The stream does not exist"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to get")
+    )
+)]
+pub async fn get_stream(
+    Path(stream): Path<String>,
+    viewer: Viewer,
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+) -> Result<Json<LiveStreamDetails>, Error> {
+    let stream = svc.get_stream(&stream).await.ok_or(Error::NotFound)?;
+
+    access::authorize_listing(&db, &stream.name, stream.visibility, &viewer).await?;
+
+    let info = stream.describe(&db).await?;
+
+    let (movie, renditions) = match &*stream.splitter.read().await {
+        Some(splitter) => (Some(splitter.movie.clone()), splitter.renditions().await),
+        None => (None, Vec::new()),
+    };
+
+    let video = movie.as_ref().and_then(|movie| {
+        movie
+            .tracks
+            .iter()
+            .find_map(|track| match &track.info.kind {
+                MediaKind::Video(video) => Some((video.width, video.height)),
+                _ => None,
+            })
+    });
+
+    Ok(Json(LiveStreamDetails {
+        info,
+        codecs: movie.as_ref().and_then(|movie| movie.codec_string()),
+        width: video.map(|(width, _)| width),
+        height: video.map(|(_, height)| height),
+        framerate: stream.ingest_stats.map(|s| s.framerate),
+        bitrate: stream.ingest_stats.map(|s| s.bitrate),
+        renditions,
+    }))
+}
+
+/// Latency statistics of a livestream.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamStats {
+    /// How many viewers the stream has.
+    viewers: usize,
+
+    /// How many of the viewers reported their latency.
+    reporting_viewers: usize,
+
+    /// The average glass-to-glass latency of the reporting viewers, in milliseconds.
+    average_latency: Option<f64>,
+
+    /// The median glass-to-glass latency of the reporting viewers, in milliseconds.
+    median_latency: Option<f64>,
+
+    /// The highest glass-to-glass latency of the reporting viewers, in milliseconds.
+    max_latency: Option<f64>,
+}
+
+impl StreamStats {
+    fn new(viewers: usize, mut latencies: Vec<f64>) -> Self {
+        latencies.sort_by(f64::total_cmp);
+
+        let average_latency = if latencies.is_empty() {
+            None
         } else {
-            0
+            Some(latencies.iter().sum::<f64>() / latencies.len() as f64)
+        };
+
+        let median_latency = match latencies.len() {
+            0 => None,
+            len if len % 2 == 1 => Some(latencies[len / 2]),
+            // the middle two values are averaged for an even number of latencies
+            len => Some((latencies[len / 2 - 1] + latencies[len / 2]) / 2.0),
         };
 
-        all_streams.push(LiveStreamInfo {
-            name: stream.name.clone(),
+        StreamStats {
             viewers,
-            is_live: splitter.is_some(),
-            started: stream.started.unix_timestamp(),
-            stopped: stream.stopped_streaming.map(|t| t.unix_timestamp()),
-        });
+            reporting_viewers: latencies.len(),
+            average_latency,
+            median_latency,
+            max_latency: latencies.last().copied(),
+        }
     }
+}
+
+/// Gets latency statistics of a livestream.
+///
+/// ### Remarks
+///
+/// Latencies are reported by players through the WebSocket of [`crate::live::get_video`], only
+/// the latest report of each current viewer is included.
+#[utoipa::path(
+    get,
+    path = "/api/stream/{stream}/stats",
+    responses(
+        (status = 200, description = "Returned the statistics", body = StreamStats),
+        (status = 401, description = "The stream is private and the viewer is not logged in"),
+        (status = 403, description = "The viewer is banned, or the stream is private and the viewer is not allowed to watch it"),
+        (status = 404, description = "The stream does not exist"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to get statistics of")
+    )
+)]
+pub async fn get_stats(
+    Path(stream): Path<String>,
+    viewer: Viewer,
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+) -> Result<Json<StreamStats>, Error> {
+    let stream = svc.get_stream(&stream).await.ok_or(Error::NotFound)?;
+
+    access::authorize_viewer(&db, &stream.name, stream.visibility, &viewer).await?;
 
-    Ok(Json(all_streams))
+    let stats = match &*stream.splitter.read().await {
+        Some(splitter) => {
+            StreamStats::new(splitter.viewer_count().await, splitter.latencies().await)
+        }
+        None => StreamStats::new(0, Vec::new()),
+    };
+
+    Ok(Json(stats))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PreviewQuery {
+    /// Only include tracks of this kind.
+    tracks: Option<TrackKind>,
 }
 
 /// Gets the most recently cached preview from a stream.
 ///
 /// ### Remarks
 ///
-/// The preview is returned as a short MP4 video.
+/// The preview is returned as a short MP4 video, or as a few seconds of MP4 audio for streams
+/// without video.
+///
+/// With `?tracks=audio` only the audio of the stream is returned as `audio/mp4`, and with
+/// `?tracks=video` only the video.
 ///
 /// This can return previews from offline streams as well.
+///
+/// Previews of private streams are only returned to viewers on the streamer's allowlist.
 #[utoipa::path(
     get,
     path = "/api/stream/{stream}/preview",
     responses(
-        (status = 200, description = "Returned preview", content_type = "video/mp4"),
-        (status = 404, description = "Did not find any previews for the given stream", content_type = "text/plain")
+        (status = 200, description = "Returned preview, which is audio/mp4 for streams without video or with `?tracks=audio`", content_type = "video/mp4"),
+        (status = 401, description = "The stream is private and the viewer is not logged in"),
+        (status = 403, description = "The viewer is banned, or the stream is private and the viewer is not allowed to watch it"),
+        (status = 404, description = "Did not find any previews for the given stream, or it has no tracks of the requested kind", content_type = "text/plain")
     ),
     params(
-        ("stream" = String, Path, description = "The stream to get the preview from")
+        ("stream" = String, Path, description = "The stream to get the preview from"),
+        ("tracks" = Option<String>, Query, description = "Only include `audio` or `video` tracks")
     )
 )]
 pub async fn get_preview(
     Path(stream): Path<String>,
+    Query(query): Query<PreviewQuery>,
+    viewer: Viewer,
+    Extension(db): Extension<Connection>,
     Extension(svc): Extension<LiveStreamService>,
 ) -> Result<Response<StreamBody<impl Stream<Item = io::Result<Bytes>>>>, Error> {
     let stream = svc.get_stream(&stream).await.ok_or(Error::NotFound)?;
 
-    let gop = stream.gop.read().await;
-    if gop.len() == 0 {
-        return Err(Error::NotFound);
-    }
-
-    let movie = stream
-        .splitter
-        .read()
-        .await
-        .as_ref()
-        .map(|s| s.movie.clone())
-        .ok_or(Error::NotFound)?;
+    access::authorize_viewer(&db, &stream.name, stream.visibility, &viewer).await?;
 
-    let mp4 = snapshot_mp4(&movie, gop.clone())?;
+    let (media_type, mp4) = stream.preview(query.tracks).await?.ok_or(Error::NotFound)?;
     let chunks = mp4
         .to_byte_spans()
         .into_iter()
@@ -416,13 +1274,329 @@ pub async fn get_preview(
     let stream = stream::iter(chunks);
 
     let mut response = Response::new(StreamBody::new(stream));
-    response
-        .headers_mut()
-        .insert("Content-Type", HeaderValue::from_static("video/mp4"));
+    response.headers_mut().insert(
+        "Content-Type",
+        HeaderValue::from_static(media_type.mp4_mime_type()),
+    );
 
     Ok(response)
 }
 
+/// A request to unlock a password protected stream.
+#[derive(ToSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UnlockRequest {
+    /// The password of the stream.
+    password: String,
+}
+
+/// Unlocks a password protected stream.
+///
+/// ### Remarks
+///
+/// On success a cookie is set that lets the viewer watch the stream for a day, or until the
+/// streamer changes the password.
+///
+/// Each address can make 10 attempts every 5 minutes.
+#[utoipa::path(
+    post,
+    path = "/api/stream/{stream}/unlock",
+    request_body = UnlockRequest,
+    responses(
+        (status = 200, description = "Unlocked the stream successfully"),
+        (status = 401, description = "The password is wrong"),
+        (status = 404, description = "The stream is not password protected"),
+        (status = 429, description = "Too many attempts to unlock the stream"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to unlock")
+    )
+)]
+pub async fn post_unlock_stream(
+    Path(stream): Path<String>,
+    viewer: Viewer,
+    Extension(db): Extension<Connection>,
+    Extension(key): Extension<SecretKey>,
+    Extension(limiter): Extension<UnlockLimiter>,
+    Json(body): Json<UnlockRequest>,
+) -> Result<impl IntoResponse, Error> {
+    limiter.check(viewer.addr, &stream)?;
+
+    let (name, token) = access::unlock_stream(&db, &key, stream, body.password).await?;
+
+    let cookie = format!(
+        "{name}={token}; Path=/api; Max-Age={}; HttpOnly; SameSite=Lax",
+        access::UNLOCK_DURATION
+    );
+
+    Ok((StatusCode::OK, [(header::SET_COOKIE, cookie)]))
+}
+
+/// A viewer that is currently watching a stream.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewerInfo {
+    /// The ID of the viewer's connection.
+    id: String,
+
+    /// The name of the viewer, or nothing if they are anonymous.
+    name: Option<String>,
+
+    /// When the viewer started watching.
+    joined: i64,
+}
+
+/// A request to ban a viewer.
+#[derive(ToSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewViewerBan {
+    /// How many seconds the ban lasts. The ban is permanent when not specified.
+    duration: Option<i64>,
+}
+
+/// A viewer that is banned from a stream.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewerBanInfo {
+    /// The ID of the ban.
+    id: i64,
+
+    /// The name of the banned viewer, or nothing if they were anonymous.
+    viewer: Option<String>,
+
+    /// When the viewer was banned.
+    created_at: i64,
+
+    /// When the ban ends, or nothing if it is permanent.
+    expires_at: Option<i64>,
+}
+
+fn require_owner(name: &str, stream: &str) -> Result<(), Error> {
+    if name.eq_ignore_ascii_case(stream) {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+/// Gets the viewers that are currently watching the user's stream.
+#[utoipa::path(
+    get,
+    path = "/api/stream/{stream}/viewers",
+    responses(
+        (status = 200, description = "Listed viewers successfully", body = [ViewerInfo]),
+        (status = 403, description = "The stream does not belong to the user"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to list viewers of")
+    )
+)]
+pub async fn get_viewers(
+    ApiAuth(payload, maybe_token, ..): ApiAuth<ReadStreams>,
+    Extension(svc): Extension<LiveStreamService>,
+    Path(stream): Path<String>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            require_owner(&payload.name, &stream)?;
+
+            let viewers = match svc.get_splitter_for_stream(&stream).await {
+                Some(splitter) => splitter.viewers().await,
+                None => Vec::new(),
+            };
+
+            let viewers = viewers
+                .into_iter()
+                .map(|session| ViewerInfo {
+                    id: session.id,
+                    name: session.name,
+                    joined: session.joined.unix_timestamp(),
+                })
+                .collect::<Vec<_>>();
+
+            Ok::<_, Error>(Json(viewers))
+        })
+        .await
+}
+
+/// Disconnects a viewer from the user's stream.
+///
+/// ### Remarks
+///
+/// The viewer is free to reconnect, ban them to prevent that.
+#[utoipa::path(
+    post,
+    path = "/api/stream/{stream}/viewers/{id}/kick",
+    responses(
+        (status = 200, description = "Kicked the viewer successfully"),
+        (status = 403, description = "The stream does not belong to the user"),
+        (status = 404, description = "The viewer is not watching the stream"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to kick the viewer from"),
+        ("id" = String, Path, description = "The ID of the viewer's connection")
+    )
+)]
+pub async fn post_kick_viewer(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(svc): Extension<LiveStreamService>,
+    Path((stream, id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            require_owner(&payload.name, &stream)?;
+
+            let splitter = svc
+                .get_splitter_for_stream(&stream)
+                .await
+                .ok_or(Error::NotFound)?;
+
+            let removed = splitter.remove_viewers(|session| session.id == id).await;
+            if removed.is_empty() {
+                return Err(Error::NotFound);
+            }
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+/// Bans a viewer from the user's stream and disconnects them.
+///
+/// ### Remarks
+///
+/// Logged in viewers are banned by name, anonymous viewers are banned by IP address. All of the
+/// viewer's connections to the stream are closed.
+#[utoipa::path(
+    post,
+    path = "/api/stream/{stream}/viewers/{id}/ban",
+    request_body = NewViewerBan,
+    responses(
+        (status = 200, description = "Banned the viewer successfully"),
+        (status = 400, description = "The duration is not positive"),
+        (status = 403, description = "The stream does not belong to the user"),
+        (status = 404, description = "The viewer is not watching the stream"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to ban the viewer from"),
+        ("id" = String, Path, description = "The ID of the viewer's connection")
+    )
+)]
+pub async fn post_ban_viewer(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+    Path((stream, id)): Path<(String, String)>,
+    Json(body): Json<NewViewerBan>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            require_owner(&payload.name, &stream)?;
+
+            let expires_at = match body.duration {
+                Some(duration) if duration <= 0 => return Err(Error::InvalidTimeframe),
+                Some(duration) => Some(OffsetDateTime::now_utc().unix_timestamp() + duration),
+                None => None,
+            };
+
+            let splitter = svc
+                .get_splitter_for_stream(&stream)
+                .await
+                .ok_or(Error::NotFound)?;
+            let session = splitter
+                .viewers()
+                .await
+                .into_iter()
+                .find(|session| session.id == id)
+                .ok_or(Error::NotFound)?;
+
+            access::ban_viewer(
+                &db,
+                payload.name,
+                session.name.clone(),
+                session.addr,
+                expires_at,
+            )
+            .await?;
+
+            splitter
+                .remove_viewers(|other| other.same_viewer(&session))
+                .await;
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+/// Gets the active bans of the user's stream.
+#[utoipa::path(
+    get,
+    path = "/api/stream/{stream}/bans",
+    responses(
+        (status = 200, description = "Listed bans successfully", body = [ViewerBanInfo]),
+        (status = 403, description = "The stream does not belong to the user"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to list bans of")
+    )
+)]
+pub async fn get_bans(
+    ApiAuth(payload, maybe_token, ..): ApiAuth<ReadStreams>,
+    Extension(db): Extension<Connection>,
+    Path(stream): Path<String>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            require_owner(&payload.name, &stream)?;
+
+            let bans = access::get_viewer_bans(&db, payload.name)
+                .await?
+                .into_iter()
+                .map(|ban| ViewerBanInfo {
+                    id: ban.id,
+                    viewer: ban.viewer,
+                    created_at: ban.created_at,
+                    expires_at: ban.expires_at,
+                })
+                .collect::<Vec<_>>();
+
+            Ok::<_, Error>(Json(bans))
+        })
+        .await
+}
+
+/// Lifts a ban from the user's stream.
+#[utoipa::path(
+    delete,
+    path = "/api/stream/{stream}/bans/{id}",
+    responses(
+        (status = 200, description = "Lifted the ban successfully"),
+        (status = 403, description = "The stream does not belong to the user"),
+        (status = 404, description = "The stream has no ban with the given ID"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to lift the ban from"),
+        ("id" = i64, Path, description = "The ID of the ban")
+    )
+)]
+pub async fn delete_ban(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Path((stream, id)): Path<(String, i64)>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            require_owner(&payload.name, &stream)?;
+
+            if !access::remove_viewer_ban(&db, payload.name, id).await? {
+                return Err(Error::NotFound);
+            }
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
 fn snapshot_mp4(movie: &Movie, packets: Vec<mediabox::Packet>) -> anyhow::Result<Span> {
     let mut fragger = FragmentedMp4Muxer::with_streams(&movie.tracks);
 
@@ -434,3 +1608,48 @@ fn snapshot_mp4(movie: &Movie, packets: Vec<mediabox::Packet>) -> anyhow::Result
 
     Ok(span)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn media_types() {
+        assert_eq!(MediaType::new(true, true), MediaType::AudioVideo);
+        assert_eq!(MediaType::new(true, false), MediaType::Video);
+        assert_eq!(MediaType::new(false, true), MediaType::Audio);
+
+        assert_eq!(MediaType::AudioVideo.mp4_mime_type(), "video/mp4");
+        assert_eq!(MediaType::Video.mp4_mime_type(), "video/mp4");
+        assert_eq!(MediaType::Audio.mp4_mime_type(), "audio/mp4");
+    }
+
+    #[test]
+    fn median_of_odd_number_of_latencies() {
+        let stats = StreamStats::new(3, vec![300.0, 100.0, 200.0]);
+
+        assert_eq!(stats.median_latency, Some(200.0));
+    }
+
+    #[test]
+    fn stats_without_reports() {
+        let stats = StreamStats::new(3, Vec::new());
+
+        assert_eq!(stats.viewers, 3);
+        assert_eq!(stats.reporting_viewers, 0);
+        assert_eq!(stats.average_latency, None);
+        assert_eq!(stats.median_latency, None);
+        assert_eq!(stats.max_latency, None);
+    }
+
+    #[test]
+    fn stats_of_latencies() {
+        let stats = StreamStats::new(5, vec![400.0, 100.0, 1000.0, 300.0]);
+
+        assert_eq!(stats.viewers, 5);
+        assert_eq!(stats.reporting_viewers, 4);
+        assert_eq!(stats.average_latency, Some(450.0));
+        assert_eq!(stats.median_latency, Some(350.0));
+        assert_eq!(stats.max_latency, Some(1000.0));
+    }
+}
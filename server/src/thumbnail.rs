@@ -0,0 +1,204 @@
+use std::{env, process::Stdio, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    extract::Path,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use bytes::Bytes;
+use time::OffsetDateTime;
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::*;
+
+use crate::{
+    access::{self, Viewer},
+    ffmpeg::ffmpeg_path,
+    stream::{LiveStreamService, MediaType, Visibility},
+    Connection, Error,
+};
+
+/// How often thumbnails are rendered when `THUMBNAIL_INTERVAL` is not set, in seconds.
+const DEFAULT_THUMBNAIL_INTERVAL: u64 = 60;
+
+/// How often to check whether a new stream has a keyframe to render its first thumbnail from.
+const FIRST_THUMBNAIL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The width thumbnails are scaled to, keeping the aspect ratio.
+const THUMBNAIL_WIDTH: u32 = 640;
+
+/// A still image of a stream.
+#[derive(Clone, Debug)]
+pub struct Thumbnail {
+    pub jpeg: Bytes,
+    pub created: OffsetDateTime,
+}
+
+fn thumbnail_interval() -> Duration {
+    let seconds = env::var("THUMBNAIL_INTERVAL")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(DEFAULT_THUMBNAIL_INTERVAL);
+
+    Duration::from_secs(seconds)
+}
+
+/// Renders thumbnails of a stream periodically until the session that started at `started`
+/// ends.
+pub async fn generate_thumbnails(svc: LiveStreamService, name: String, started: OffsetDateTime) {
+    let interval = thumbnail_interval();
+    let mut rendered = false;
+
+    loop {
+        // render the first thumbnail as soon as possible so it can be shown in notifications
+        tokio::time::sleep(if rendered {
+            interval
+        } else {
+            FIRST_THUMBNAIL_POLL_INTERVAL
+        })
+        .await;
+
+        let stream = match svc.get_stream(&name).await {
+            Some(stream) if stream.is_live() && stream.started() == started => stream,
+            _ => return,
+        };
+
+        let mp4 = match stream.preview(None).await {
+            Ok(Some((MediaType::Audio, _))) => return,
+            Ok(Some((_, mp4))) => mp4,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Failed to create preview for thumbnail: {e:?}");
+                rendered = true;
+                continue;
+            }
+        };
+
+        // don't retry failures more often than the interval
+        rendered = true;
+
+        match render_thumbnail(mp4.to_slice().into_owned()).await {
+            Ok(jpeg) => {
+                debug!("Rendered {} byte thumbnail", jpeg.len());
+
+                stream
+                    .set_thumbnail(Thumbnail {
+                        jpeg,
+                        created: OffsetDateTime::now_utc(),
+                    })
+                    .await;
+            }
+            Err(e) => warn!("Failed to render thumbnail: {e:?}"),
+        }
+    }
+}
+
+/// Decodes the first frame of an MP4 to a JPEG with ffmpeg.
+async fn render_thumbnail(mp4: Vec<u8>) -> anyhow::Result<Bytes> {
+    let scale = format!("scale={THUMBNAIL_WIDTH}:-2");
+    let mut child = Command::new(ffmpeg_path())
+        .args(["-loglevel", "error", "-f", "mp4", "-i", "pipe:0"])
+        .args(["-frames:v", "1", "-vf", &scale, "-q:v", "5"])
+        .args(["-f", "image2pipe", "-vcodec", "mjpeg", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start ffmpeg")?;
+
+    let mut stdin = child.stdin.take().context("Missing ffmpeg stdin")?;
+    let writer = tokio::spawn(async move {
+        // ffmpeg closes its input once it has a frame, so a broken pipe is expected
+        let _ = stdin.write_all(&mp4).await;
+    });
+
+    let output = child
+        .wait_with_output()
+        .await
+        .context("Failed to wait for ffmpeg")?;
+    let _ = writer.await;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        anyhow::bail!(
+            "ffmpeg exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(Bytes::from(output.stdout))
+}
+
+/// Gets a JPEG thumbnail of a stream.
+///
+/// ### Remarks
+///
+/// Thumbnails are rendered from the latest keyframe every minute by default, which can be
+/// changed with `THUMBNAIL_INTERVAL`. Streams without video have no thumbnail.
+///
+/// The thumbnail has an `ETag`, and `If-None-Match` can be used to avoid downloading it again.
+/// Thumbnails of private and password protected streams, and of streams with banned viewers, are
+/// not stored in shared caches.
+#[utoipa::path(
+    get,
+    path = "/api/stream/{stream}/thumbnail.jpg",
+    responses(
+        (status = 200, description = "Returned thumbnail", content_type = "image/jpeg"),
+        (status = 304, description = "The thumbnail has not changed"),
+        (status = 401, description = "The stream is private and the viewer is not logged in"),
+        (status = 403, description = "The viewer is banned, or the stream is private and the viewer is not allowed to watch it"),
+        (status = 404, description = "The stream does not exist or has no thumbnail yet"),
+    ),
+    params(
+        ("stream" = String, Path, description = "The stream to get the thumbnail of")
+    )
+)]
+pub async fn get_thumbnail(
+    Path(stream): Path<String>,
+    viewer: Viewer,
+    headers: HeaderMap,
+    Extension(db): Extension<Connection>,
+    Extension(svc): Extension<LiveStreamService>,
+) -> Result<Response, Error> {
+    let stream = svc.get_stream(&stream).await.ok_or(Error::NotFound)?;
+
+    access::authorize_viewer(&db, stream.name(), stream.visibility(), &viewer).await?;
+
+    let thumbnail = stream.thumbnail().await.ok_or(Error::NotFound)?;
+
+    // shared caches would hand the thumbnail to viewers that aren't allowed to see it, unless
+    // everyone is
+    let locked = access::get_locked_streams(&db).await?;
+    let bans = access::get_viewer_bans(&db, stream.name().to_string()).await?;
+    let is_shareable = stream.visibility() != Visibility::Private
+        && !locked.iter().any(|l| l.eq_ignore_ascii_case(stream.name()))
+        && bans.is_empty();
+    let cache_control = format!(
+        "{}, max-age={}",
+        if is_shareable { "public" } else { "private" },
+        thumbnail_interval().as_secs()
+    );
+    let etag = format!("\"{}\"", thumbnail.created.unix_timestamp_nanos());
+
+    let headers_out = [
+        (header::CACHE_CONTROL, cache_control),
+        (header::ETAG, etag.clone()),
+    ];
+
+    let is_cached = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if is_cached {
+        return Ok((StatusCode::NOT_MODIFIED, headers_out).into_response());
+    }
+
+    Ok((
+        headers_out,
+        [(header::CONTENT_TYPE, "image/jpeg")],
+        thumbnail.jpeg,
+    )
+        .into_response())
+}
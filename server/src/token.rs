@@ -0,0 +1,445 @@
+use std::marker::PhantomData;
+
+use anyhow::Context;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    http::{header, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{Authorize, Caller, MaybeSession},
+    Connection, Error,
+};
+
+/// Every token starts with this prefix, which makes them easy to spot in leaked secrets.
+const TOKEN_PREFIX: &str = "scuffed_";
+
+/// The most tokens a single account can have.
+const MAX_TOKENS: i64 = 25;
+
+const MAX_TOKEN_NAME_LENGTH: usize = 100;
+
+/// Something an API token is allowed to do.
+#[derive(ToSchema, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TokenScope {
+    /// Watch streams and read the viewers and bans of the account's stream.
+    ReadStreams,
+
+    /// Read and regenerate the account's stream key.
+    ManageKeys,
+
+    /// Manage the account's notification subscription and preferences.
+    ManageNotifications,
+}
+
+impl TokenScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::ReadStreams => "readStreams",
+            TokenScope::ManageKeys => "manageKeys",
+            TokenScope::ManageNotifications => "manageNotifications",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "readStreams" => Some(TokenScope::ReadStreams),
+            "manageKeys" => Some(TokenScope::ManageKeys),
+            "manageNotifications" => Some(TokenScope::ManageNotifications),
+            _ => None,
+        }
+    }
+}
+
+/// The scope an endpoint requires when it is called with an API token.
+pub trait Scope: Send + Sync + 'static {
+    const SCOPE: TokenScope;
+}
+
+pub struct ReadStreams;
+impl Scope for ReadStreams {
+    const SCOPE: TokenScope = TokenScope::ReadStreams;
+}
+
+pub struct ManageKeys;
+impl Scope for ManageKeys {
+    const SCOPE: TokenScope = TokenScope::ManageKeys;
+}
+
+pub struct ManageNotifications;
+impl Scope for ManageNotifications {
+    const SCOPE: TokenScope = TokenScope::ManageNotifications;
+}
+
+/// Authorizes a request with either the session cookie or an API token with the scope `S`.
+///
+/// ### Remarks
+///
+/// API tokens are passed in the `Authorization: Bearer <token>` header.
+pub struct ApiAuth<S: Scope>(pub Caller, pub MaybeSession, pub PhantomData<S>);
+
+#[async_trait]
+impl<St: Send + Sync, S: Scope> FromRequestParts<St> for ApiAuth<S> {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &St) -> Result<Self, Self::Rejection> {
+        if let Some(token) = bearer_token(parts) {
+            let db = parts
+                .extensions
+                .get::<Connection>()
+                .cloned()
+                .ok_or_else(|| Error::InternalError(anyhow::anyhow!("Missing database")))
+                .map_err(IntoResponse::into_response)?;
+
+            let (name, scopes) = authenticate(&db, token)
+                .await
+                .map_err(|e| Error::from(e).into_response())?
+                .ok_or_else(|| Error::Unathorized.into_response())?;
+
+            if !scopes.contains(&S::SCOPE) {
+                return Err(Error::Forbidden.into_response());
+            }
+
+            let caller = Caller {
+                name,
+                groups: Vec::new(),
+            };
+
+            return Ok(ApiAuth(caller, MaybeSession::none(), PhantomData));
+        }
+
+        let Authorize(caller, maybe_session) = Authorize::from_request_parts(parts, state).await?;
+
+        Ok(ApiAuth(caller, maybe_session, PhantomData))
+    }
+}
+
+/// Gets the token from an `Authorization: Bearer <token>` header.
+pub fn bearer_token(parts: &Parts) -> Option<String> {
+    parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+/// Looks up the account and scopes of an API token, and records that it was used.
+pub async fn authenticate(
+    db: &Connection,
+    token: String,
+) -> anyhow::Result<Option<(String, Vec<TokenScope>)>> {
+    let hash = hash_token(&token);
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    db.call(move |conn| {
+        let found = conn
+            .query_row(
+                "SELECT id, username, scopes FROM api_tokens WHERE token_hash = ?1",
+                params![hash],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        let (id, name, scopes) = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        conn.execute(
+            "UPDATE api_tokens SET last_used_at = ?1 WHERE id = ?2",
+            params![now, id],
+        )?;
+
+        Ok::<_, rusqlite::Error>(Some((name, parse_scopes(&scopes))))
+    })
+    .await
+    .context("Failed to authenticate API token")
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn parse_scopes(scopes: &str) -> Vec<TokenScope> {
+    scopes.split(',').filter_map(TokenScope::parse).collect()
+}
+
+fn get_new_token() -> String {
+    let mut secret_bytes = [0u8; 32];
+    StdRng::from_entropy().fill_bytes(&mut secret_bytes[..]);
+
+    format!("{TOKEN_PREFIX}{}", hex::encode(secret_bytes))
+}
+
+/// An API token of the current user.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiTokenInfo {
+    /// The ID of the token.
+    id: i64,
+
+    /// A name to remember the token by.
+    name: String,
+
+    /// What the token is allowed to do.
+    scopes: Vec<TokenScope>,
+
+    /// When the token was created.
+    created_at: i64,
+
+    /// When the token was last used, if ever.
+    last_used_at: Option<i64>,
+}
+
+/// A request to create an API token.
+#[derive(ToSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewApiToken {
+    /// A name to remember the token by.
+    name: String,
+
+    /// What the token is allowed to do. At least one scope is required.
+    scopes: Vec<TokenScope>,
+}
+
+/// A newly created API token.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedApiToken {
+    /// The token to pass in the `Authorization: Bearer <token>` header.
+    ///
+    /// Only a hash of the token is stored, so this is the only time it can be seen.
+    token: String,
+
+    /// Information about the token.
+    info: ApiTokenInfo,
+}
+
+/// Lists the API tokens of the current user.
+#[utoipa::path(
+    get,
+    path = "/api/account/tokens",
+    responses(
+        (status = 200, description = "Listed API tokens successfully", body = [ApiTokenInfo]),
+    )
+)]
+pub async fn get_tokens(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let name = payload.name;
+            let tokens = db
+                .call(move |conn| {
+                    let mut stmt = conn.prepare(
+                        "SELECT id, name, scopes, created_at, last_used_at FROM api_tokens \
+                        WHERE username = ?1 \
+                        ORDER BY created_at DESC",
+                    )?;
+
+                    let tokens = stmt
+                        .query_map(params![name], |row| {
+                            Ok(ApiTokenInfo {
+                                id: row.get(0)?,
+                                name: row.get(1)?,
+                                scopes: parse_scopes(&row.get::<_, String>(2)?),
+                                created_at: row.get(3)?,
+                                last_used_at: row.get(4)?,
+                            })
+                        })?
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    Ok::<_, rusqlite::Error>(tokens)
+                })
+                .await
+                .context("Failed to query API tokens")?;
+
+            Ok::<_, Error>(Json(tokens))
+        })
+        .await
+}
+
+/// Creates an API token for the current user.
+///
+/// ### Remarks
+///
+/// API tokens can't be used to manage API tokens, this always requires the session cookie.
+#[utoipa::path(
+    post,
+    path = "/api/account/tokens",
+    request_body = NewApiToken,
+    responses(
+        (status = 201, description = "Created API token successfully", body = CreatedApiToken),
+        (status = 400, description = "The name is too long, no scopes were given or the account has too many tokens"),
+    )
+)]
+pub async fn post_token(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Json(body): Json<NewApiToken>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            if body.name.chars().count() > MAX_TOKEN_NAME_LENGTH {
+                return Err(Error::TooManyCharacters {
+                    field: "name",
+                    maximum_length: MAX_TOKEN_NAME_LENGTH as u64,
+                });
+            }
+
+            if body.scopes.is_empty() {
+                return Err(Error::NoTokenScopes);
+            }
+
+            let token = get_new_token();
+            let hash = hash_token(&token);
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+
+            let mut scopes = Vec::new();
+            for scope in body.scopes {
+                if !scopes.contains(&scope) {
+                    scopes.push(scope);
+                }
+            }
+            let stored_scopes = scopes
+                .iter()
+                .map(TokenScope::as_str)
+                .collect::<Vec<_>>()
+                .join(",");
+
+            let name = payload.name;
+            let token_name = body.name.clone();
+            let id = db
+                .call(move |conn| {
+                    let count: i64 = conn.query_row(
+                        "SELECT COUNT(*) FROM api_tokens WHERE username = ?1",
+                        params![name],
+                        |row| row.get(0),
+                    )?;
+                    if count >= MAX_TOKENS {
+                        return Ok(None);
+                    }
+
+                    conn.execute(
+                        "INSERT INTO api_tokens \
+                        (username, name, token_hash, scopes, created_at) \
+                        VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![name, token_name, hash, stored_scopes, now],
+                    )?;
+
+                    Ok::<_, rusqlite::Error>(Some(conn.last_insert_rowid()))
+                })
+                .await
+                .context("Failed to insert API token")?
+                .ok_or(Error::TooManyTokens)?;
+
+            let created = CreatedApiToken {
+                token,
+                info: ApiTokenInfo {
+                    id,
+                    name: body.name,
+                    scopes,
+                    created_at: now,
+                    last_used_at: None,
+                },
+            };
+
+            Ok((StatusCode::CREATED, Json(created)))
+        })
+        .await
+}
+
+/// Revokes an API token of the current user.
+#[utoipa::path(
+    delete,
+    path = "/api/account/tokens/{id}",
+    responses(
+        (status = 200, description = "Revoked API token successfully"),
+        (status = 404, description = "The user has no API token with the given ID"),
+    ),
+    params(
+        ("id" = i64, Path, description = "The ID of the token")
+    )
+)]
+pub async fn delete_token(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let name = payload.name;
+            let removed = db
+                .call(move |conn| {
+                    conn.execute(
+                        "DELETE FROM api_tokens WHERE id = ?1 AND username = ?2",
+                        params![id, name],
+                    )
+                })
+                .await
+                .context("Failed to delete API token")?;
+
+            if removed == 0 {
+                return Err(Error::NotFound);
+            }
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_scopes() {
+        assert_eq!(
+            parse_scopes("readStreams,manageNotifications"),
+            vec![TokenScope::ReadStreams, TokenScope::ManageNotifications]
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_scopes() {
+        assert_eq!(
+            parse_scopes("manageKeys,deleteEverything,"),
+            vec![TokenScope::ManageKeys]
+        );
+        assert_eq!(parse_scopes(""), vec![]);
+    }
+
+    #[test]
+    fn scopes_round_trip() {
+        let scopes = [
+            TokenScope::ReadStreams,
+            TokenScope::ManageKeys,
+            TokenScope::ManageNotifications,
+        ];
+        let stored = scopes
+            .iter()
+            .map(TokenScope::as_str)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        assert_eq!(parse_scopes(&stored), scopes);
+    }
+}
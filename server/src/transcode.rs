@@ -0,0 +1,303 @@
+use std::{env, net::SocketAddr, process::Stdio};
+
+use anyhow::Context;
+use mediabox::{
+    format::{mp4::FragmentedMp4Muxer, rtmp::RtmpRequest, Movie},
+    MediaKind,
+};
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::*;
+
+use crate::{
+    event::StreamEvent,
+    ffmpeg::{self, ffmpeg_path},
+    stream::LiveStreamService,
+};
+
+/// The RTMP app transcoders send renditions to.
+pub const RENDITION_APP: &str = "rendition";
+
+/// A lower quality version of a stream.
+#[derive(Clone, Debug)]
+struct Rendition {
+    /// The name viewers select the rendition by, e.g. `720p`.
+    name: String,
+
+    /// The height of the video.
+    height: u32,
+
+    /// The video bitrate, in kilobits per second.
+    bitrate: u32,
+}
+
+/// Reads the renditions to transcode streams into from `TRANSCODE_RENDITIONS`, e.g.
+/// `720p,480p`. Streams are not transcoded when it is not set.
+fn renditions_from_env() -> Vec<Rendition> {
+    match env::var("TRANSCODE_RENDITIONS") {
+        Ok(renditions) => parse_renditions(&renditions),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn parse_renditions(renditions: &str) -> Vec<Rendition> {
+    renditions
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            match name
+                .strip_suffix('p')
+                .and_then(|height| height.parse().ok())
+            {
+                Some(height) => Some(Rendition {
+                    name: name.to_string(),
+                    height,
+                    bitrate: default_bitrate(height),
+                }),
+                None => {
+                    warn!("Ignoring invalid rendition {name:?}, expected e.g. 720p");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn default_bitrate(height: u32) -> u32 {
+    match height {
+        1080.. => 4500,
+        720.. => 2500,
+        480.. => 1200,
+        360.. => 700,
+        _ => 400,
+    }
+}
+
+/// Starts transcoding a stream into every configured rendition that is smaller than the
+/// stream itself.
+pub async fn start_transcoders(
+    svc: &LiveStreamService,
+    rtmp_addr: SocketAddr,
+    stream: &str,
+    movie: &Movie,
+) {
+    let source_height = movie
+        .tracks
+        .iter()
+        .find_map(|track| match &track.info.kind {
+            MediaKind::Video(video) => Some(video.height),
+            _ => None,
+        });
+    let source_height = match source_height {
+        Some(height) => height,
+        None => return,
+    };
+
+    // transcoders connect to the RTMP listener like a streamer would
+    let rtmp_addr = if rtmp_addr.ip().is_unspecified() {
+        SocketAddr::from(([127, 0, 0, 1], rtmp_addr.port()))
+    } else {
+        rtmp_addr
+    };
+
+    for rendition in renditions_from_env() {
+        if rendition.height >= source_height {
+            continue;
+        }
+
+        let svc = svc.clone();
+        let stream = stream.to_string();
+        let span = debug_span!("transcode", rendition = %rendition.name);
+
+        tokio::spawn(
+            async move {
+                if let Err(e) = transcode(svc, rtmp_addr, stream, rendition).await {
+                    warn!("Transcoding failed: {e:?}");
+                }
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// Feeds a stream to ffmpeg until it stops, which sends the rendition back over RTMP.
+async fn transcode(
+    svc: LiveStreamService,
+    rtmp_addr: SocketAddr,
+    stream: String,
+    rendition: Rendition,
+) -> anyhow::Result<()> {
+    let splitter = svc
+        .get_splitter_for_stream(&stream)
+        .await
+        .context("Stream has no splitter")?;
+    let mut events = svc.subscribe();
+    let (movie, mut receiver) = splitter.tap().await;
+
+    let key = svc
+        .create_rendition_key(stream.clone(), rendition.name.clone())
+        .await;
+    let url = format!("rtmp://{rtmp_addr}/{RENDITION_APP}/{key}");
+
+    info!("Transcoding into {}", rendition.name);
+
+    let scale = format!("scale=-2:{}", rendition.height);
+    let bitrate = format!("{}k", rendition.bitrate);
+    let buffer_size = format!("{}k", rendition.bitrate * 2);
+    let mut child = Command::new(ffmpeg_path())
+        .args(["-loglevel", "error", "-f", "mp4", "-i", "pipe:0"])
+        .args(["-map", "0:v:0", "-map", "0:a?", "-c:a", "copy"])
+        .args([
+            "-c:v",
+            "libx264",
+            "-preset",
+            "veryfast",
+            "-tune",
+            "zerolatency",
+        ])
+        .args(["-vf", &scale, "-b:v", &bitrate, "-maxrate", &bitrate])
+        .args(["-bufsize", &buffer_size])
+        // keyframes at the same time as the original let players switch between renditions
+        .args(["-force_key_frames", "source", "-f", "flv", &url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start ffmpeg")?;
+    ffmpeg::log_stderr(&mut child);
+
+    let mut stdin = child.stdin.take().context("Missing ffmpeg stdin")?;
+    let mut fragger = FragmentedMp4Muxer::with_streams(&movie.tracks);
+    let mut synced = false;
+
+    loop {
+        let pkt = tokio::select! {
+            pkt = receiver.recv() => match pkt {
+                Some(pkt) => pkt.packet,
+                // ffmpeg fell too far behind
+                None => break,
+            },
+            event = events.recv() => match event {
+                Some(StreamEvent::Stopped { stream: s }) if s.eq_ignore_ascii_case(&stream) => break,
+                Some(_) => continue,
+                None => break,
+            },
+        };
+
+        if !synced {
+            if !(pkt.track.is_video() && pkt.key) {
+                continue;
+            }
+
+            synced = true;
+            stdin
+                .write_all(&fragger.initialization_segment()?.to_slice())
+                .await?;
+        }
+
+        stdin
+            .write_all(&fragger.write_media_segment(pkt)?.to_slice())
+            .await?;
+    }
+
+    // closing the input lets ffmpeg finish, which ends the rendition
+    drop(stdin);
+    svc.take_rendition_key(&key).await;
+
+    let status = child.wait().await.context("Failed to wait for ffmpeg")?;
+    if !status.success() {
+        anyhow::bail!("ffmpeg exited with {status}");
+    }
+
+    Ok(())
+}
+
+/// Receives a rendition from a transcoder and sends it to the viewers that selected it.
+pub async fn handle_rendition(svc: LiveStreamService, request: RtmpRequest) -> anyhow::Result<()> {
+    if !request.addr().ip().is_loopback() {
+        anyhow::bail!("Rejected rendition from {}", request.addr());
+    }
+
+    let (stream, rendition) = svc
+        .take_rendition_key(request.key())
+        .await
+        .context("Unknown rendition key")?;
+
+    let span = debug_span!("rendition", stream = %stream, rendition = %rendition);
+
+    let fut = async move {
+        let mut session = request.authenticate().await?;
+
+        let tracks = session.streams().await?;
+        let movie = Movie {
+            tracks,
+            attachments: Vec::new(),
+        };
+
+        let mut splitter = svc
+            .get_splitter_for_stream(&stream)
+            .await
+            .context("Stream has no splitter")?;
+        splitter.add_rendition(rendition.clone(), movie).await;
+
+        debug!("Receiving rendition");
+
+        let result = loop {
+            match session.read_frame().await {
+                Ok(pkt) => splitter.write_rendition_packet(&rendition, pkt).await,
+                Err(e) => break e,
+            }
+        };
+
+        splitter.remove_rendition(&rendition).await;
+
+        // ffmpeg disconnecting also ends up here
+        debug!("Stopped receiving rendition: {result:?}");
+
+        // viewers of a stream that stopped wait for it to come back instead
+        let is_live = svc.get_stream(&stream).await.is_some_and(|s| s.is_live());
+        if is_live {
+            warn!("Rendition stopped while the stream is live");
+            splitter.disconnect_rendition(&rendition).await;
+        }
+
+        Ok(())
+    };
+
+    fut.instrument(span).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_renditions() {
+        let renditions = parse_renditions(" 720p, 480p,,360");
+
+        let names = renditions
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["720p", "480p"]);
+        assert_eq!(renditions[0].height, 720);
+        assert_eq!(renditions[0].bitrate, 2500);
+    }
+
+    #[test]
+    fn ignores_invalid_renditions() {
+        assert!(parse_renditions("").is_empty());
+        assert!(parse_renditions("hd,p,-1p").is_empty());
+    }
+
+    #[test]
+    fn bitrates_grow_with_height() {
+        assert_eq!(default_bitrate(2160), 4500);
+        assert_eq!(default_bitrate(1080), 4500);
+        assert_eq!(default_bitrate(1079), 2500);
+        assert_eq!(default_bitrate(480), 1200);
+        assert_eq!(default_bitrate(360), 700);
+        assert_eq!(default_bitrate(240), 400);
+    }
+}
@@ -0,0 +1,738 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use crate::{
+    admin,
+    auth::Authorize,
+    event::{EventSubscriber, StreamEvent},
+    stream::LiveStreamService,
+    Connection, Error,
+};
+
+use anyhow::Context;
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{delete, get},
+    Extension, Json, Router,
+};
+use hmac::{Hmac, Mac};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use reqwest::{redirect, Url};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+/// How many times a delivery is attempted before giving up.
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+
+/// How many deliveries are returned from the delivery log.
+const DELIVERY_LOG_LIMIT: u32 = 50;
+
+pub fn api_route() -> Router {
+    Router::new()
+        .route("/", get(get_webhooks).post(post_webhook))
+        .route("/:id", delete(delete_webhook))
+        .route("/:id/deliveries", get(get_webhook_deliveries))
+}
+
+/// A stream lifecycle event that can be delivered to a webhook.
+#[derive(ToSchema, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEventKind {
+    StreamStarted,
+    StreamStopped,
+}
+
+impl WebhookEventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventKind::StreamStarted => "streamStarted",
+            WebhookEventKind::StreamStopped => "streamStopped",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "streamStarted" => Some(WebhookEventKind::StreamStarted),
+            "streamStopped" => Some(WebhookEventKind::StreamStopped),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPayload {
+    event: WebhookEventKind,
+    stream: String,
+    timestamp: i64,
+}
+
+/// Delivers stream lifecycle events to registered webhooks.
+#[derive(Clone)]
+pub struct Webhooks {
+    db: Connection,
+    svc: LiveStreamService,
+}
+
+impl Webhooks {
+    pub fn new(db: Connection, svc: LiveStreamService) -> Self {
+        Webhooks { db, svc }
+    }
+
+    /// Delivers stream events to webhooks until the event bus closes.
+    pub async fn listen(self, mut events: EventSubscriber) {
+        while let Some(event) = events.recv().await {
+            match event {
+                StreamEvent::Started { stream } => {
+                    self.dispatch(WebhookEventKind::StreamStarted, stream)
+                }
+                StreamEvent::Stopped { stream } => {
+                    self.dispatch(WebhookEventKind::StreamStopped, stream)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends `event` for `stream` to every matching webhook in the background.
+    fn dispatch(&self, event: WebhookEventKind, stream: String) {
+        let webhooks = self.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = webhooks.dispatch_impl(event, stream).await {
+                warn!("Failed to dispatch webhooks: {e:?}");
+            }
+        });
+    }
+
+    async fn dispatch_impl(&self, event: WebhookEventKind, stream: String) -> anyhow::Result<()> {
+        let payload = WebhookPayload {
+            event,
+            stream: stream.clone(),
+            timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+        };
+        let payload = serde_json::to_string(&payload)?;
+
+        // only the owner hears about streams that are not public
+        let is_public = self.svc.is_public(&stream).await;
+        let targets = get_webhooks_for_stream(&self.db, stream, is_public).await?;
+        for webhook in targets {
+            if !webhook.events.contains(&event) {
+                continue;
+            }
+
+            let webhooks = self.clone();
+            let payload = payload.clone();
+            tokio::spawn(async move {
+                webhooks.deliver(webhook, event, payload).await;
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn deliver(&self, webhook: WebhookInfo, event: WebhookEventKind, payload: String) {
+        let signature = sign_payload(&webhook.secret, payload.as_bytes());
+
+        for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+            debug!("Delivering {} to webhook {}", event.as_str(), webhook.id);
+
+            // the host might resolve to another address than when the webhook was registered
+            let result = match check_webhook_url(&webhook.url)
+                .await
+                .and_then(|checked| checked.client().map_err(anyhow::Error::from))
+            {
+                Ok(client) => client
+                    .post(&webhook.url)
+                    .header("Content-Type", "application/json")
+                    .header("X-Scuffed-Event", event.as_str())
+                    .header("X-Scuffed-Signature", format!("sha256={signature}"))
+                    .timeout(Duration::from_secs(10))
+                    .body(payload.clone())
+                    .send()
+                    .await
+                    .map_err(anyhow::Error::from),
+                Err(e) => Err(e),
+            };
+
+            let (status_code, error) = match &result {
+                Ok(response) => (Some(response.status().as_u16()), None),
+                Err(e) => (None, Some(format!("{e:#}"))),
+            };
+
+            if let Err(e) = log_delivery(
+                &self.db,
+                webhook.id,
+                event,
+                payload.clone(),
+                attempt,
+                status_code,
+                error,
+            )
+            .await
+            {
+                warn!("Failed to log webhook delivery: {e:?}");
+            }
+
+            match result {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => {
+                    warn!(
+                        "Webhook {} responded with {} on attempt {attempt}",
+                        webhook.id,
+                        response.status()
+                    );
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to deliver webhook {} on attempt {attempt}: {e:#}",
+                        webhook.id
+                    );
+                }
+            }
+
+            if attempt < MAX_DELIVERY_ATTEMPTS {
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+        }
+    }
+}
+
+fn sign_payload(secret: &str, payload: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(payload);
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Whether an address is reachable from the internet, as opposed to e.g. the server itself or
+/// its local network.
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast())
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_address(IpAddr::V4(ip));
+            }
+
+            let segment = ip.segments()[0];
+            let is_unique_local = segment & 0xfe00 == 0xfc00;
+            let is_link_local = segment & 0xffc0 == 0xfe80;
+
+            !(ip.is_loopback() || ip.is_unspecified() || is_unique_local || is_link_local)
+        }
+    }
+}
+
+/// A webhook URL whose host only resolved to public addresses.
+struct CheckedUrl {
+    host: String,
+    addresses: Vec<SocketAddr>,
+}
+
+impl CheckedUrl {
+    /// Creates a client that connects to the checked addresses, since resolving the host again
+    /// could return other addresses by then.
+    fn client(&self) -> reqwest::Result<reqwest::Client> {
+        reqwest::Client::builder()
+            // a redirect could lead to an address that was not checked
+            .redirect(redirect::Policy::none())
+            .resolve_to_addrs(&self.host, &self.addresses)
+            .build()
+    }
+}
+
+/// Checks that a webhook URL uses HTTP and only resolves to public addresses, so that webhooks
+/// can't be used to make requests to services on the server's network.
+async fn check_webhook_url(url: &str) -> anyhow::Result<CheckedUrl> {
+    let (host, port) = parse_webhook_url(url)?;
+
+    // IP addresses are not looked up
+    let addresses = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .with_context(|| format!("Failed to resolve {host:?}"))?
+        .collect::<Vec<_>>();
+    check_addresses(&addresses)?;
+
+    Ok(CheckedUrl { host, addresses })
+}
+
+/// Gets the host and port of an HTTP URL.
+fn parse_webhook_url(url: &str) -> anyhow::Result<(String, u16)> {
+    let url = Url::parse(url).context("Invalid URL")?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        anyhow::bail!("Unsupported scheme {:?}", url.scheme());
+    }
+
+    let port = url.port_or_known_default().context("URL has no port")?;
+    let host = url.host_str().context("URL has no host")?;
+
+    // IPv6 hosts are in brackets
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    Ok((host.to_string(), port))
+}
+
+fn check_addresses(addresses: &[SocketAddr]) -> anyhow::Result<()> {
+    if addresses.is_empty() {
+        anyhow::bail!("Host has no addresses");
+    }
+
+    if let Some(address) = addresses.iter().find(|a| !is_public_address(a.ip())) {
+        anyhow::bail!("Host resolves to non-public address {}", address.ip());
+    }
+
+    Ok(())
+}
+
+fn get_new_webhook_secret() -> String {
+    let mut secret_bytes = [0u8; 32];
+    StdRng::from_entropy().fill_bytes(&mut secret_bytes[..]);
+
+    hex::encode(secret_bytes)
+}
+
+/// A registered webhook.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookInfo {
+    /// The ID of the webhook.
+    id: i64,
+
+    /// The URL events are posted to.
+    url: String,
+
+    /// The secret used to sign payloads.
+    ///
+    /// Every request carries a `X-Scuffed-Signature: sha256=<hex>` header containing the
+    /// HMAC-SHA256 of the request body.
+    secret: String,
+
+    /// The events the webhook receives.
+    events: Vec<WebhookEventKind>,
+
+    /// Whether the webhook receives events for every public stream rather than only the owner's.
+    all_streams: bool,
+
+    /// When the webhook was created.
+    created_at: i64,
+}
+
+/// A request to register a webhook.
+#[derive(ToSchema, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewWebhook {
+    /// The URL events are posted to.
+    url: String,
+
+    /// The events the webhook receives.
+    events: Vec<WebhookEventKind>,
+
+    /// Whether the webhook receives events for every public stream rather than only the
+    /// owner's. Only admins can register such webhooks.
+    #[serde(default)]
+    all_streams: bool,
+}
+
+/// A single delivery attempt of a webhook.
+#[derive(ToSchema, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    /// The event that was delivered.
+    event: String,
+
+    /// The JSON payload that was sent.
+    payload: String,
+
+    /// Which attempt this was, starting at 1.
+    attempt: u32,
+
+    /// The HTTP status code the webhook responded with, if any.
+    status_code: Option<u16>,
+
+    /// The error that occurred while delivering, if any.
+    error: Option<String>,
+
+    /// When the delivery was attempted.
+    delivered_at: i64,
+}
+
+/// Lists the webhooks of the current user.
+#[utoipa::path(
+    get,
+    path = "/api/webhook",
+    responses(
+        (status = 200, description = "Listed webhooks successfully", body = [WebhookInfo]),
+    )
+)]
+pub async fn get_webhooks(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let webhooks = get_webhooks_for_user(&db, payload.name).await?;
+
+            Ok::<_, Error>(Json(webhooks))
+        })
+        .await
+}
+
+/// Registers a new webhook for the current user.
+///
+/// ### Remarks
+///
+/// The URL must use `http` or `https`, and its host must not resolve to a loopback, private,
+/// link-local or unspecified address. This is checked again before every delivery.
+#[utoipa::path(
+    post,
+    path = "/api/webhook",
+    request_body = NewWebhook,
+    responses(
+        (status = 201, description = "Registered webhook successfully", body = WebhookInfo),
+        (status = 400, description = "The webhook URL is not valid"),
+        (status = 403, description = "The webhook receives events for all streams and the user is not an admin"),
+    )
+)]
+pub async fn post_webhook(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Json(body): Json<NewWebhook>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            if body.all_streams && !admin::is_admin(&payload.groups) {
+                return Err(Error::Forbidden);
+            }
+
+            if let Err(e) = check_webhook_url(&body.url).await {
+                debug!("Rejected webhook URL {:?}: {e:#}", body.url);
+                return Err(Error::InvalidWebhookUrl);
+            }
+
+            let webhook = add_webhook(&db, payload.name, body).await?;
+
+            Ok::<_, Error>((StatusCode::CREATED, Json(webhook)))
+        })
+        .await
+}
+
+/// Removes a webhook.
+#[utoipa::path(
+    delete,
+    path = "/api/webhook/{id}",
+    responses(
+        (status = 200, description = "Removed webhook successfully"),
+        (status = 404, description = "The user has no webhook with the given ID"),
+    ),
+    params(
+        ("id" = i64, Path, description = "The ID of the webhook")
+    )
+)]
+pub async fn delete_webhook(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let name = payload.name;
+            let removed = db
+                .call(move |conn| {
+                    conn.execute(
+                        "DELETE FROM webhooks WHERE id = ?1 AND username = ?2",
+                        params![id, name],
+                    )
+                })
+                .await
+                .context("Failed to delete webhook")?;
+
+            if removed == 0 {
+                return Err(Error::NotFound);
+            }
+
+            Ok::<_, Error>(StatusCode::OK)
+        })
+        .await
+}
+
+/// Lists the most recent delivery attempts of a webhook.
+#[utoipa::path(
+    get,
+    path = "/api/webhook/{id}/deliveries",
+    responses(
+        (status = 200, description = "Listed deliveries successfully", body = [WebhookDelivery]),
+        (status = 404, description = "The user has no webhook with the given ID"),
+    ),
+    params(
+        ("id" = i64, Path, description = "The ID of the webhook")
+    )
+)]
+pub async fn get_webhook_deliveries(
+    Authorize(payload, maybe_token): Authorize,
+    Extension(db): Extension<Connection>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let deliveries = get_deliveries(&db, payload.name, id)
+                .await?
+                .ok_or(Error::NotFound)?;
+
+            Ok::<_, Error>(Json(deliveries))
+        })
+        .await
+}
+
+fn webhook_from_row(row: &rusqlite::Row) -> rusqlite::Result<WebhookInfo> {
+    let events: String = row.get(3)?;
+
+    Ok(WebhookInfo {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        secret: row.get(2)?,
+        events: events
+            .split(',')
+            .filter_map(WebhookEventKind::parse)
+            .collect(),
+        all_streams: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+async fn get_webhooks_for_user(db: &Connection, name: String) -> anyhow::Result<Vec<WebhookInfo>> {
+    db.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, url, secret, events, all_streams, created_at \
+            FROM webhooks \
+            WHERE username = ?1",
+        )?;
+
+        let rows = stmt
+            .query_map(params![name], webhook_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok::<_, rusqlite::Error>(rows)
+    })
+    .await
+    .context("Failed to query webhooks")
+}
+
+/// Gets the webhooks of the owner of a stream, and those for all streams if `include_all_streams`
+/// is set.
+async fn get_webhooks_for_stream(
+    db: &Connection,
+    stream: String,
+    include_all_streams: bool,
+) -> anyhow::Result<Vec<WebhookInfo>> {
+    db.call(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, url, secret, events, all_streams, created_at \
+            FROM webhooks \
+            WHERE username = ?1 OR (all_streams = 1 AND ?2)",
+        )?;
+
+        let rows = stmt
+            .query_map(params![stream, include_all_streams], webhook_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok::<_, rusqlite::Error>(rows)
+    })
+    .await
+    .context("Failed to query webhooks")
+}
+
+async fn add_webhook(
+    db: &Connection,
+    name: String,
+    webhook: NewWebhook,
+) -> anyhow::Result<WebhookInfo> {
+    let secret = get_new_webhook_secret();
+    let created_at = OffsetDateTime::now_utc().unix_timestamp();
+    let events = webhook
+        .events
+        .iter()
+        .map(|e| e.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    db.call(move |conn| {
+        conn.execute(
+            "INSERT INTO webhooks \
+            (username, url, secret, events, all_streams, created_at) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                &name,
+                &webhook.url,
+                &secret,
+                &events,
+                webhook.all_streams,
+                created_at
+            ],
+        )
+        .context("Failed to insert webhook")?;
+
+        debug!("Added webhook for {name:?}");
+
+        Ok(WebhookInfo {
+            id: conn.last_insert_rowid(),
+            url: webhook.url,
+            secret,
+            events: webhook.events,
+            all_streams: webhook.all_streams,
+            created_at,
+        })
+    })
+    .await
+}
+
+async fn get_deliveries(
+    db: &Connection,
+    name: String,
+    id: i64,
+) -> anyhow::Result<Option<Vec<WebhookDelivery>>> {
+    db.call(move |conn| {
+        let owned = conn
+            .query_row(
+                "SELECT id FROM webhooks WHERE id = ?1 AND username = ?2",
+                params![id, name],
+                |_r| Ok(()),
+            )
+            .optional()?;
+        if owned.is_none() {
+            return Ok(None);
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT event, payload, attempt, status_code, error, delivered_at \
+            FROM webhook_deliveries \
+            WHERE webhook_id = ?1 \
+            ORDER BY id DESC \
+            LIMIT ?2",
+        )?;
+
+        let rows = stmt
+            .query_map(params![id, DELIVERY_LOG_LIMIT], |row| {
+                Ok(WebhookDelivery {
+                    event: row.get(0)?,
+                    payload: row.get(1)?,
+                    attempt: row.get(2)?,
+                    status_code: row.get(3)?,
+                    error: row.get(4)?,
+                    delivered_at: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok::<_, rusqlite::Error>(Some(rows))
+    })
+    .await
+    .context("Failed to query webhook deliveries")
+}
+
+async fn log_delivery(
+    db: &Connection,
+    webhook_id: i64,
+    event: WebhookEventKind,
+    payload: String,
+    attempt: u32,
+    status_code: Option<u16>,
+    error: Option<String>,
+) -> anyhow::Result<()> {
+    let delivered_at = OffsetDateTime::now_utc().unix_timestamp();
+
+    db.call(move |conn| {
+        conn.execute(
+            "INSERT INTO webhook_deliveries \
+            (webhook_id, event, payload, attempt, status_code, error, delivered_at) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                webhook_id,
+                event.as_str(),
+                payload,
+                attempt,
+                status_code,
+                error,
+                delivered_at
+            ],
+        )
+        .context("Failed to insert webhook delivery")?;
+
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_addresses() {
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700::1111"] {
+            assert!(is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn non_public_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn parses_webhook_urls() {
+        assert_eq!(
+            parse_webhook_url("https://example.com/hook").unwrap(),
+            ("example.com".to_string(), 443)
+        );
+        assert_eq!(
+            parse_webhook_url("http://[2606:4700::1111]:8080/").unwrap(),
+            ("2606:4700::1111".to_string(), 8080)
+        );
+
+        assert!(parse_webhook_url("ftp://1.1.1.1/hook").is_err());
+        assert!(parse_webhook_url("not a url").is_err());
+    }
+
+    #[test]
+    fn only_public_addresses_are_allowed() {
+        let addr = |ip: &str| SocketAddr::new(ip.parse().unwrap(), 80);
+
+        assert!(check_addresses(&[addr("1.1.1.1"), addr("2606:4700::1111")]).is_ok());
+
+        assert!(check_addresses(&[]).is_err());
+        assert!(check_addresses(&[addr("1.1.1.1"), addr("127.0.0.1")]).is_err());
+        assert!(check_addresses(&[addr("169.254.169.254")]).is_err());
+    }
+}